@@ -0,0 +1,302 @@
+// Backups of the executable, kept beside it and named after the build they were taken from:
+//
+//     Borderlands2.exe.<sha1>.bak     a full copy
+//     Borderlands2.exe.<sha1>.minbak  just the original bytes at each change's offset
+//
+// A minimal backup is enough to undo this tool's own changes, but not to recover from any
+// other kind of damage to the executable.
+//
+// With --backup-dir they're kept there instead, which may well be on another filesystem.
+
+use {
+    std::{
+        error::Error,
+        fmt::Write as _,
+        fs::{self, File, OpenOptions},
+        io::{self, Read, Seek, SeekFrom, Write},
+        path::{Path, PathBuf},
+        sync::OnceLock,
+    },
+    crate::{
+        check_free_space, check_room, err_box, hash_file, parse_hex, refuse_writes, write_error,
+        PatcherError, Version,
+    },
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BackupKind {
+    Full, Minimal
+}
+
+impl BackupKind {
+    fn extension(self) -> &'static str {
+        match self {
+            BackupKind::Full    => "bak",
+            BackupKind::Minimal => "minbak",
+        }
+    }
+}
+
+pub struct Backup {
+    pub path: PathBuf,
+    pub hash: String,
+    pub kind: BackupKind,
+}
+
+/// The original bytes at one offset, as recorded in a minimal backup.
+struct SavedBytes {
+    offset: u64,
+    bytes:  Vec<u8>,
+}
+
+static BACKUP_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Keeps backups in `dir` rather than beside the executable, for the rest of the run.
+pub fn set_dir(dir: PathBuf) {
+    // only the first setting counts; there's only ever one
+    let _ = BACKUP_DIR.set(dir);
+}
+
+const MINIMAL_HEADER: &str = "bl-patcher minimal backup";
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+fn bad_backup<T>(path: &Path) -> Result<T, Box<dyn Error>> {
+    err_box(PatcherError::BadBackup { path: path.to_string_lossy().to_string() })
+}
+
+impl Backup {
+    /// Where backups of `exe` are kept.
+    fn dir(exe: &Path) -> Option<&Path> {
+        BACKUP_DIR.get().map(PathBuf::as_path).or_else(|| exe.parent())
+    }
+
+    fn path_for(exe: &Path, hash: &str, kind: BackupKind) -> PathBuf {
+        let name = format!("{}.{}.{}", file_name(exe), hash, kind.extension());
+        match Backup::dir(exe) {
+            Some(dir) => dir.join(name),
+            None => PathBuf::from(name)
+        }
+    }
+
+    /// Every backup of `exe`.
+    pub fn list(exe: &Path) -> Result<Vec<Backup>, Box<dyn Error>> {
+        let dir = match Backup::dir(exe) {
+            Some(dir) => dir,
+            None => return Ok(Vec::new())
+        };
+        let prefix = format!("{}.", file_name(exe));
+
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            // a backup directory that hasn't been used yet
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into())
+        };
+
+        let mut backups = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            let name = file_name(&path);
+
+            let (hash, extension) = match name.strip_prefix(&prefix).and_then(|rest| rest.split_once('.')) {
+                Some(parts) => parts,
+                None => continue
+            };
+            let kind = match extension {
+                "bak"    => BackupKind::Full,
+                "minbak" => BackupKind::Minimal,
+                _ => continue
+            };
+            if hash.len() != 40 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                continue;
+            }
+
+            backups.push(Backup { hash: hash.to_string(), path, kind });
+        }
+
+        backups.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(backups)
+    }
+
+    /// The backup of build `hash`, preferring a full copy over a minimal one.
+    pub fn find(exe: &Path, hash: &str) -> Result<Option<Backup>, Box<dyn Error>> {
+        let mut backups: Vec<Backup> = Backup::list(exe)?
+            .into_iter()
+            .filter(|backup| backup.hash == hash)
+            .collect();
+        backups.sort_by_key(|backup| backup.kind != BackupKind::Full);
+        Ok(backups.into_iter().next())
+    }
+
+    /// `find`, but only a backup that `verify` passes; a damaged one is warned about and passed
+    /// over, so that it's replaced rather than relied on.
+    pub fn find_sound(exe: &Path, hash: &str) -> Result<Option<Backup>, Box<dyn Error>> {
+        let mut backups: Vec<Backup> = Backup::list(exe)?
+            .into_iter()
+            .filter(|backup| backup.hash == hash)
+            .collect();
+        backups.sort_by_key(|backup| backup.kind != BackupKind::Full);
+        for backup in backups {
+            if backup.verify()?.1 {
+                return Ok(Some(backup));
+            }
+            eprintln!("Warning: ignoring damaged backup {}", backup.path.to_string_lossy());
+        }
+        Ok(None)
+    }
+
+    /// Copies `exe` whole, whatever it is; `hash` is what it hashes to now.
+    pub fn create_full(exe: &Path, hash: &str) -> Result<Backup, Box<dyn Error>> {
+        refuse_writes()?;
+        let path = Backup::path_for(exe, hash, BackupKind::Full);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        // write under a temporary name first, so an interrupted backup never looks like a good one
+        let temp = path.with_extension("partial");
+        check_free_space(&path, fs::metadata(exe)?.len())?;
+        if let Err(e) = fs::copy(exe, &temp) {
+            let _ = fs::remove_file(&temp);
+            return Err(write_error(e, &temp));
+        }
+        fs::rename(&temp, &path)?;
+
+        Ok(Backup { path, hash: hash.to_string(), kind: BackupKind::Full })
+    }
+
+    /// Backs up `exe`, which must currently be the unpatched build of `version`.
+    pub fn create(exe: &Path, file: &mut File, version: &Version, kind: BackupKind)
+        -> Result<Backup, Box<dyn Error>>
+    {
+        refuse_writes()?;
+        let hash = version.unpatched_hash;
+        if kind == BackupKind::Full {
+            return Backup::create_full(exe, hash);
+        }
+
+        let path = Backup::path_for(exe, hash, kind);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut text = format!("{}\nsha1 {}\n", MINIMAL_HEADER, hash);
+        for change in version.changes {
+            let mut bytes = vec![0u8; change.original.len()];
+            file.seek(SeekFrom::Start(change.offset))?;
+            file.read_exact(&mut bytes)?;
+
+            write!(text, "{:x} ", change.offset)?;
+            for byte in bytes {
+                write!(text, "{:02x}", byte)?;
+            }
+            text.push('\n');
+        }
+
+        // as above, never leave a partial backup under the real name
+        let temp = path.with_extension("partial");
+        if let Err(e) = fs::write(&temp, text) {
+            let _ = fs::remove_file(&temp);
+            return Err(write_error(e, &temp));
+        }
+        fs::rename(&temp, &path)?;
+
+        Ok(Backup { path, hash: hash.to_string(), kind })
+    }
+
+    fn read_minimal(&self) -> Result<Vec<SavedBytes>, Box<dyn Error>> {
+        let text = fs::read_to_string(&self.path)?;
+        let mut lines = text.lines();
+
+        if lines.next() != Some(MINIMAL_HEADER) {
+            return bad_backup(&self.path);
+        }
+        if lines.next() != Some(format!("sha1 {}", self.hash).as_str()) {
+            return bad_backup(&self.path);
+        }
+
+        let mut changes = Vec::new();
+        for line in lines {
+            let parsed = line.split_once(' ').and_then(|(offset, bytes)| {
+                let offset = u64::from_str_radix(offset, 16).ok()?;
+                let bytes = parse_hex(bytes)?;
+                Some(SavedBytes { offset, bytes })
+            });
+
+            match parsed {
+                Some(change) => changes.push(change),
+                None => return bad_backup(&self.path)
+            }
+        }
+
+        Ok(changes)
+    }
+
+    /// What a full backup actually hashes to, or `None` for a minimal one, which can't be
+    /// hashed as a build; and whether the backup is sound: a full one hashes as recorded,
+    /// a minimal one is readable.
+    pub fn verify(&self) -> Result<(Option<String>, bool), Box<dyn Error>> {
+        match self.kind {
+            BackupKind::Full => {
+                let actual = hash_file(&mut File::open(&self.path)?)?.to_string();
+                let valid = actual == self.hash;
+                Ok((Some(actual), valid))
+            }
+            BackupKind::Minimal => Ok((None, self.read_minimal().is_ok()))
+        }
+    }
+
+    /// Puts `exe` back the way it was when this backup was taken, and checks that it hashes
+    /// as the recorded build afterwards.
+    pub fn restore(&self, exe: &Path) -> Result<(), Box<dyn Error>> {
+        self.restore_within(exe, check_free_space)
+    }
+
+    /// `restore`, after a patch that failed partway: the --min-free-space margin is left out,
+    /// since an exe that's a mix of two builds is worse than a full disk.
+    pub fn roll_back(&self, exe: &Path) -> Result<(), Box<dyn Error>> {
+        self.restore_within(exe, check_room)
+    }
+
+    fn restore_within<C>(&self, exe: &Path, check_space: C) -> Result<(), Box<dyn Error>>
+        where C: Fn(&Path, u64) -> Result<(), Box<dyn Error>>
+    {
+        refuse_writes()?;
+        match self.kind {
+            BackupKind::Full => {
+                // copy beside the exe, then rename into place, so the exe is never left
+                // half-written; being in the same directory, the rename never crosses filesystems
+                let temp = exe.with_file_name(format!("{}.restoring", file_name(exe)));
+                check_space(exe, fs::metadata(&self.path)?.len())?;
+                let staged = fs::copy(&self.path, &temp).and_then(|_| fs::rename(&temp, exe));
+                if let Err(e) = staged {
+                    let _ = fs::remove_file(&temp);
+                    return Err(write_error(e, &temp));
+                }
+            }
+            BackupKind::Minimal => {
+                let changes = self.read_minimal()?;
+                let mut file = OpenOptions::new().write(true).open(exe)?;
+                for saved in changes {
+                    file.seek(SeekFrom::Start(saved.offset))?;
+                    file.write_all(&saved.bytes).map_err(|e| write_error(e, exe))?;
+                }
+            }
+        }
+
+        let found = hash_file(&mut File::open(exe)?)?.to_string();
+        if found != self.hash {
+            return err_box(PatcherError::BackupMismatch {
+                path:     self.path.to_string_lossy().to_string(),
+                expected: self.hash.clone(),
+                found,
+            });
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,222 @@
+// Just enough PE/ELF header parsing to tell what kind of executable we've been handed.
+
+use std::{
+    fmt::{self, Display, Formatter},
+    io::{self, Read, Seek, SeekFrom},
+    str::FromStr,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Arch {
+    X86, X86_64, Arm, Arm64, Unknown
+}
+
+impl Display for Arch {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let name = match self {
+            Arch::X86     => "x86",
+            Arch::X86_64  => "x86_64",
+            Arch::Arm     => "arm",
+            Arch::Arm64   => "arm64",
+            Arch::Unknown => "unknown",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Arch {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Arch, ()> {
+        match s {
+            "x86"    => Ok(Arch::X86),
+            "x86_64" => Ok(Arch::X86_64),
+            "arm"    => Ok(Arch::Arm),
+            "arm64"  => Ok(Arch::Arm64),
+            _        => Err(())
+        }
+    }
+}
+
+fn read_bytes<R: Read + Seek, const N: usize>(file: &mut R, offset: u64) -> io::Result<[u8; N]> {
+    let mut buf = [0u8; N];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+/// Reads the machine type from a PE or ELF header. Anything else (or anything too short to
+/// be either) comes back as `Arch::Unknown`.
+pub fn read_arch<R: Read + Seek>(file: &mut R) -> io::Result<Arch> {
+    let magic: [u8; 4] = match read_bytes(file, 0) {
+        Ok(magic) => magic,
+        Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(Arch::Unknown),
+        Err(e) => return Err(e)
+    };
+
+    let arch = match magic {
+        [b'M', b'Z', ..] => read_pe_arch(file),
+        [0x7f, b'E', b'L', b'F'] => read_elf_arch(file),
+        _ => Ok(Arch::Unknown)
+    };
+
+    match arch {
+        Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(Arch::Unknown),
+        arch => arch
+    }
+}
+
+/// A file that ends partway through its headers isn't a PE file we can make anything of,
+/// the same as one that isn't PE at all.
+fn truncated_as_none<T>(result: io::Result<Option<T>>) -> io::Result<Option<T>> {
+    match result {
+        Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        result => result
+    }
+}
+
+/// Finds the COFF header of a PE file, if that's what this is.
+fn find_coff_header<R: Read + Seek>(file: &mut R) -> io::Result<Option<u64>> {
+    truncated_as_none((|| {
+        if read_bytes::<_, 2>(file, 0)? != *b"MZ" {
+            return Ok(None);
+        }
+
+        // e_lfanew points at the "PE\0\0" signature, followed by the COFF header
+        let pe_offset = u32::from_le_bytes(read_bytes(file, 0x3c)?) as u64;
+        if read_bytes::<_, 4>(file, pe_offset)? != *b"PE\0\0" {
+            return Ok(None);
+        }
+        Ok(Some(pe_offset + 4))
+    })())
+}
+
+fn read_pe_arch<R: Read + Seek>(file: &mut R) -> io::Result<Arch> {
+    let coff = match find_coff_header(file)? {
+        Some(coff) => coff,
+        None => return Ok(Arch::Unknown)
+    };
+
+    let arch = match u16::from_le_bytes(read_bytes(file, coff)?) {
+        0x014c          => Arch::X86,
+        0x8664          => Arch::X86_64,
+        0x01c0 | 0x01c4 => Arch::Arm,
+        0xaa64          => Arch::Arm64,
+        _               => Arch::Unknown
+    };
+    Ok(arch)
+}
+
+fn read_elf_arch<R: Read + Seek>(file: &mut R) -> io::Result<Arch> {
+    // EI_DATA says which byte order e_machine is in
+    let [data] = read_bytes(file, 5)?;
+    let raw = read_bytes(file, 0x12)?;
+    let machine = match data {
+        1 => u16::from_le_bytes(raw),
+        2 => u16::from_be_bytes(raw),
+        _ => return Ok(Arch::Unknown)
+    };
+
+    let arch = match machine {
+        0x03 => Arch::X86,
+        0x3e => Arch::X86_64,
+        0x28 => Arch::Arm,
+        0xb7 => Arch::Arm64,
+        _    => Arch::Unknown
+    };
+    Ok(arch)
+}
+
+/// A span of bytes in the file.
+pub struct Range {
+    pub start: u64,
+    pub len:   u64,
+}
+
+/// The raw data of every section in a PE file, in file order. This is what gets mapped; it
+/// leaves out the headers (whose checksum and certificate directory change when a build is
+/// re-signed) along with the certificate table and anything else in the overlay.
+pub fn pe_section_ranges<R: Read + Seek>(file: &mut R) -> io::Result<Option<Vec<Range>>> {
+    let coff = match find_coff_header(file)? {
+        Some(coff) => coff,
+        None => return Ok(None)
+    };
+
+    truncated_as_none((|| {
+        let section_count = u16::from_le_bytes(read_bytes(file, coff + 2)?) as u64;
+        let optional_header_size = u16::from_le_bytes(read_bytes(file, coff + 16)?) as u64;
+        let section_table = coff + 20 + optional_header_size;
+
+        let mut ranges = Vec::new();
+        for index in 0 .. section_count {
+            let header = section_table + index * 40;
+            let len   = u32::from_le_bytes(read_bytes(file, header + 16)?) as u64;
+            let start = u32::from_le_bytes(read_bytes(file, header + 20)?) as u64;
+            if len != 0 {
+                ranges.push(Range { start, len });
+            }
+        }
+
+        ranges.sort_by_key(|range| range.start);
+        Ok(Some(ranges))
+    })())
+}
+
+/// Where the optional header's CheckSum field is, in a PE file that has one.
+pub fn pe_checksum_offset<R: Read + Seek>(file: &mut R) -> io::Result<Option<u64>> {
+    let coff = match find_coff_header(file)? {
+        Some(coff) => coff,
+        None => return Ok(None)
+    };
+
+    // the same place in PE32 and PE32+ optional headers
+    truncated_as_none((|| {
+        let optional_header_size = u16::from_le_bytes(read_bytes(file, coff + 16)?) as u64;
+        if optional_header_size < 68 {
+            return Ok(None);
+        }
+        Ok(Some(coff + 20 + 64))
+    })())
+}
+
+/// The checksum of a whole PE file as the loader computes it: a folded sum of its 16-bit
+/// words, with the CheckSum field at `field` counted as zero, plus the file's length.
+pub fn pe_checksum<R: Read + Seek>(file: &mut R, field: u64) -> io::Result<u32> {
+    file.seek(SeekFrom::Start(0))?;
+
+    let mut sum: u64 = 0;
+    let mut len: u64 = 0;
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        // fill the whole buffer, so only the last chunk can end on an odd offset
+        let mut count = 0;
+        while count < buf.len() {
+            match file.read(&mut buf[count ..])? {
+                0 => break,
+                more => count += more
+            }
+        }
+        if count == 0 {
+            break;
+        }
+        let chunk = &mut buf[.. count];
+
+        for (index, byte) in chunk.iter_mut().enumerate() {
+            let offset = len + index as u64;
+            if offset >= field && offset < field + 4 {
+                *byte = 0;
+            }
+        }
+
+        // an odd last byte is padded out with zero
+        for word in chunk.chunks(2) {
+            let word = u16::from_le_bytes([word[0], word.get(1).copied().unwrap_or(0)]);
+            sum += word as u64;
+            sum = (sum & 0xffff) + (sum >> 16);
+        }
+        len += count as u64;
+    }
+
+    sum = (sum & 0xffff) + (sum >> 16);
+    Ok((sum + len) as u32)
+}
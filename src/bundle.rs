@@ -0,0 +1,193 @@
+// Community patch bundles (.blpatch): a zip of version definitions (*.toml, in the format
+// described in defs.rs) plus a `signature` entry holding a raw 64-byte ed25519 signature.
+// The signed message is every definition's file name and contents, in name order:
+//
+//     <name> 0x00 <length as u64 LE> <contents>  <name> 0x00 ...
+//
+// Signatures are checked against the public keys listed in <config>/trusted-keys, one
+// hex-encoded key per line, with '#' starting a comment.
+
+use {
+    std::{
+        convert::TryFrom,
+        error::Error,
+        fs::{self, File},
+        io::Read,
+        path::{Path, PathBuf},
+    },
+    ed25519_dalek::{Signature, VerifyingKey},
+    crate::{
+        config_dir, embedded, err_box, parse_hex, PatcherError, VERSIONS,
+        defs::{self, Definition},
+    },
+};
+
+fn bad_bundle<T>(path: &Path, reason: &str) -> Result<T, Box<dyn Error>> {
+    err_box(PatcherError::BadBundle {
+        path:   path.to_string_lossy().to_string(),
+        reason: reason.to_string()
+    })
+}
+
+fn load_trusted_keys() -> Result<Vec<VerifyingKey>, Box<dyn Error>> {
+    let keys_path = config_dir()?.join("trusted-keys");
+    let no_keys = || err_box(PatcherError::NoTrustedKeys {
+        path: keys_path.to_string_lossy().to_string()
+    });
+
+    let text = match fs::read_to_string(&keys_path) {
+        Ok(text) => text,
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => return no_keys(),
+        Err(e) => return Err(e.into())
+    };
+
+    let mut keys = Vec::new();
+    for line in text.lines() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let key = parse_hex(line)
+            .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+            .and_then(|bytes| VerifyingKey::from_bytes(&bytes).ok());
+        match key {
+            Some(key) => keys.push(key),
+            None => return err_box(PatcherError::BadTrustedKey {
+                path: keys_path.to_string_lossy().to_string(),
+                key:  line.to_string()
+            })
+        }
+    }
+
+    if keys.is_empty() {
+        return no_keys();
+    }
+    Ok(keys)
+}
+
+/// Lays out named entries the way a bundle signs them: each name, a NUL, the length of the
+/// contents and the contents themselves.
+pub fn encode_entries(entries: &[(String, Vec<u8>)]) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    for (name, contents) in entries {
+        encoded.extend_from_slice(name.as_bytes());
+        encoded.push(0);
+        encoded.extend_from_slice(&(contents.len() as u64).to_le_bytes());
+        encoded.extend_from_slice(contents);
+    }
+    encoded
+}
+
+/// Checks the bundle at `path`, then copies its definitions into versions.d.
+pub fn import(path: &Path) -> Result<(), Box<dyn Error>> {
+    let mut archive = match zip::ZipArchive::new(File::open(path)?) {
+        Ok(archive) => archive,
+        Err(e) => return bad_bundle(path, &e.to_string())
+    };
+
+    let mut signature = None;
+    let mut files: Vec<(String, Vec<u8>)> = Vec::new();
+    for index in 0 .. archive.len() {
+        let mut entry = archive.by_index(index)?;
+        if entry.is_dir() {
+            continue;
+        }
+
+        // only flat names; nothing in a bundle gets to pick where it lands
+        let name = match entry.enclosed_name() {
+            Some(name) if name.components().count() == 1 => name.to_string_lossy().to_string(),
+            _ => return bad_bundle(path, "entries must not be in subdirectories")
+        };
+
+        let mut contents = Vec::new();
+        entry.read_to_end(&mut contents)?;
+
+        if name == "signature" {
+            signature = Some(contents);
+        }
+        else if name.ends_with(".toml") {
+            files.push((name, contents));
+        }
+        else {
+            return bad_bundle(path, &format!("unexpected entry \"{}\"", name));
+        }
+    }
+    files.sort();
+
+    if files.is_empty() {
+        return bad_bundle(path, "no definitions");
+    }
+
+    // check the signature before looking inside anything
+    let signature = match signature.as_deref().map(Signature::from_slice) {
+        Some(Ok(signature)) => signature,
+        Some(Err(_)) => return bad_bundle(path, "malformed signature"),
+        None => return bad_bundle(path, "not signed")
+    };
+
+    let message = encode_entries(&files);
+    let trusted = load_trusted_keys()?.iter()
+        .any(|key| key.verify_strict(&message, &signature).is_ok());
+    if !trusted {
+        return err_box(PatcherError::UntrustedBundle { path: path.to_string_lossy().to_string() });
+    }
+
+    let mut defs: Vec<(String, String, Definition)> = Vec::new();
+    for (name, contents) in files {
+        let text = match String::from_utf8(contents) {
+            Ok(text) => text,
+            Err(_) => return bad_bundle(path, &format!("{} is not UTF-8", name))
+        };
+        let def = Definition::parse(&text, &path.join(&name))?;
+        defs.push((name, text, def));
+    }
+
+    // a hash has to pick out exactly one build, so nothing may clash with what we already
+    // know, except the definitions this import is about to replace
+    let versions_dir = defs::versions_dir()?;
+    let existing = defs::load_dir(&versions_dir)?;
+
+    let mut known: Vec<(String, String)> = Vec::new();
+    for version in VERSIONS.iter() {
+        for hash in version.hashes() {
+            known.push((hash.to_string(), "built-in versions".to_string()));
+        }
+    }
+    // nor with a definition embedded in this executable, which no import can replace
+    for (origin, def) in embedded::load()? {
+        for hash in def.hashes() {
+            known.push((hash.to_string(), origin.to_string_lossy().to_string()));
+        }
+    }
+    for (existing_path, def) in &existing {
+        let replaced = defs.iter()
+            .any(|(name, _, _)| existing_path.file_name().is_some_and(|existing| *existing == **name));
+        if !replaced {
+            for hash in def.hashes() {
+                known.push((hash.to_string(), existing_path.to_string_lossy().to_string()));
+            }
+        }
+    }
+    for (name, _, def) in &defs {
+        for hash in def.hashes() {
+            if let Some((_, owner)) = known.iter().find(|(known, _)| known == hash) {
+                return err_box(PatcherError::HashCollision {
+                    hash:  hash.to_string(),
+                    owner: owner.clone()
+                });
+            }
+            known.push((hash.to_string(), path.join(name).to_string_lossy().to_string()));
+        }
+    }
+
+    fs::create_dir_all(&versions_dir)?;
+    for (name, text, def) in defs {
+        let dest: PathBuf = versions_dir.join(&name);
+        fs::write(&dest, text)?;
+        eprintln!("Added build {} ({}, {} changes) as {}",
+            def.unpatched_hash, def.arch, def.change_count(), dest.to_string_lossy());
+    }
+
+    Ok(())
+}
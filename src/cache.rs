@@ -0,0 +1,82 @@
+// Remembers where each game was found, in <config>/install-cache.json, so later runs can
+// skip searching the Steam libraries. An entry is only trusted while its exe still exists
+// and is still a build we know; otherwise we fall back to a full search.
+
+use {
+    std::{
+        collections::BTreeMap,
+        error::Error,
+        fs::{self, File},
+        path::{Path, PathBuf},
+    },
+    serde::{Deserialize, Serialize},
+    crate::{config_dir, get_exe_state, HashScope, InstallLayout},
+};
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    library_root: PathBuf,
+    steamapps:    PathBuf,
+    install_dir:  PathBuf,
+    exe_path:     PathBuf,
+    // found through a non-Steam shortcut, so `exe_path` is the exe itself, not the usual one
+    #[serde(default)]
+    shortcut:     bool,
+}
+
+/// Keyed by appid.
+type Cache = BTreeMap<String, Entry>;
+
+fn cache_path() -> Result<PathBuf, Box<dyn Error>> {
+    Ok(config_dir()?.join("install-cache.json"))
+}
+
+/// A missing or unreadable cache is just an empty one.
+fn load() -> Cache {
+    cache_path().ok()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|text| serde_json::from_str(&text).ok())
+        .unwrap_or_default()
+}
+
+/// The install is stored as found, without any --exe or configured subpath, which is applied
+/// here before the exe is checked; so a single run with --exe doesn't stick. An exe found
+/// through a shortcut is already the one to use, so it's left alone.
+pub fn lookup(appid: u32, scope: HashScope, exe_subpath: Option<&Path>) -> Option<InstallLayout> {
+    let entry = load().remove(&appid.to_string())?;
+
+    let layout = InstallLayout {
+        library_root: entry.library_root,
+        steamapps:    entry.steamapps,
+        install_dir:  entry.install_dir,
+        exe_path:     entry.exe_path,
+    };
+    let layout = match exe_subpath {
+        Some(subpath) if !entry.shortcut => layout.with_exe(subpath),
+        _ => layout
+    };
+
+    let mut file = File::open(&layout.exe_path).ok()?;
+    get_exe_state(&mut file, scope).ok()?;
+    Some(layout)
+}
+
+/// `layout` should be the install as found, before any exe subpath, or the one a shortcut
+/// pointed at; see `lookup`.
+pub fn store(appid: u32, layout: &InstallLayout, shortcut: bool) -> Result<(), Box<dyn Error>> {
+    let mut cache = load();
+    cache.insert(appid.to_string(), Entry {
+        library_root: layout.library_root.clone(),
+        steamapps:    layout.steamapps.clone(),
+        install_dir:  layout.install_dir.clone(),
+        exe_path:     layout.exe_path.clone(),
+        shortcut,
+    });
+
+    let path = cache_path()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, serde_json::to_string_pretty(&cache)?)?;
+    Ok(())
+}
@@ -0,0 +1,58 @@
+// Settings that persist between runs, in <config>/config.toml. For now that's just where
+// each game's executable is within its install, for localized or renamed executables:
+//
+//     [games.49520]
+//     exe = "Binaries/Win32/Borderlands2.exe"
+//
+// --exe overrides this for a single run.
+
+use {
+    std::{
+        collections::BTreeMap,
+        error::Error,
+        fs,
+        path::PathBuf,
+    },
+    serde::Deserialize,
+    crate::{config_dir, err_box, PatcherError},
+};
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    // keyed by appid
+    #[serde(default)]
+    games: BTreeMap<String, Game>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Game {
+    #[serde(default)]
+    exe: Option<PathBuf>,
+}
+
+impl Config {
+    /// Reads the config file. Not having one is the same as an empty one.
+    pub fn load() -> Result<Config, Box<dyn Error>> {
+        let path = config_dir()?.join("config.toml");
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Config::default()),
+            Err(e) => return Err(e.into())
+        };
+
+        match toml::from_str(&text) {
+            Ok(config) => Ok(config),
+            Err(e) => err_box(PatcherError::BadConfig {
+                path:   path.to_string_lossy().to_string(),
+                reason: e.message().to_string()
+            })
+        }
+    }
+
+    /// Where the executable of game `appid` is within its install, if configured.
+    pub fn exe_subpath(&self, appid: u32) -> Option<PathBuf> {
+        self.games.get(&appid.to_string())?.exe.clone()
+    }
+}
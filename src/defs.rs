@@ -0,0 +1,398 @@
+// Version definitions kept outside the binary, in <config>/versions.d/*.toml, one build per
+// file:
+//
+//     schema_version = 1
+//     arch           = "x86"
+//     unpatched_hash = "bc1d695c6fdb3dea491b367f73bbb045c316b32e"
+//     patched_hash   = "fc8afce04782532b0fe7a70a80ee1070da858e32"
+//
+//     [[changes]]
+//     offset   = 0x012f_8b90
+//     original = [0x73]
+//     patch    = [0x00]
+//
+// `schema_version` is the version of this format the definition is written for. One for a
+// later version than this build of the tool understands is refused rather than misread;
+// one without it is taken to be version 1, the first.
+//
+// Instead of bytes, an x86 change's `patch` may be an operation like "nop" or "jmp_over 3",
+// which is filled out to the length of `original`; see `expand`.
+//
+// A build may be given a `name`, and so may each change, so the same change can be matched
+// up across builds (see --compare-versions). A change may also have a short `description`
+// of what it does once applied ("dev commands enabled"), give the `mnemonic` of the
+// instruction expected at its offset, and the bytes just `before` and `after` its original
+// bytes, which --auto-offset uses to find it in shifted builds. The unpatched file's `size`
+// may be given for the same reason.
+//
+// `unpatched_code_hash` and `patched_code_hash` may also be given, as on the built-in
+// versions. All of the hashes are SHA-1 unless `algo` says otherwise, though "sha1" is the
+// only algorithm so far.
+//
+// Loaded definitions are added to the built-in `VERSIONS` for the rest of the run, after any
+// embedded in the patcher itself (see embedded.rs).
+
+use {
+    std::{
+        error::Error,
+        fs, iter,
+        path::{Path, PathBuf},
+        sync::OnceLock,
+    },
+    serde::Deserialize,
+    crate::{
+        check_changes, config_dir, err_box, Change, Context, HashAlgo, PatcherError, Version, VERSIONS,
+        binary::Arch,
+    },
+};
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ChangeDef {
+    #[serde(default)]
+    name:        Option<String>,
+    #[serde(default)]
+    description: Option<String>,
+    offset:      u64,
+    original:    Vec<u8>,
+    #[serde(rename = "patch")]
+    patch_def:   PatchDef,
+    // `patch_def` as bytes, once parsed
+    #[serde(skip)]
+    patch:       Vec<u8>,
+    #[serde(default)]
+    mnemonic:    Option<String>,
+    #[serde(default)]
+    before:      Vec<u8>,
+    #[serde(default)]
+    after:       Vec<u8>,
+}
+
+/// The newest version of the definition format this build understands.
+const SCHEMA_VERSION: i64 = 1;
+
+fn newer_schema(version: i64) -> String {
+    format!("written for schema version {}, but this version of bl-patcher only understands up to {}; \
+        update bl-patcher", version, SCHEMA_VERSION)
+}
+
+/// A change's patch: either its bytes, or one of the operations `expand` knows.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PatchDef {
+    Bytes(Vec<u8>),
+    Op(String),
+}
+
+const NOP: u8 = 0x90;
+const RET: u8 = 0xc3;
+const JMP_SHORT: u8 = 0xeb;
+
+/// Turns a symbolic patch into bytes, exactly as long as `original`:
+///
+///     "nop"           fill with NOPs
+///     "nop <n>"       the same, checking that's n bytes
+///     "ret"           return, then NOPs
+///     "jmp_over <n>"  a short jump over the next n bytes, then NOPs
+fn expand(op: &str, original: &[u8]) -> Result<Vec<u8>, String> {
+    let len = original.len();
+    let words: Vec<&str> = op.split_whitespace().collect();
+    let count = |word: &str| parse_int(word).ok_or_else(|| format!("\"{}\": bad count \"{}\"", op, word));
+
+    let mut bytes = match words[..] {
+        ["nop"] => Vec::new(),
+        ["nop", n] => {
+            let n = count(n)?;
+            if n != len as u64 {
+                return Err(format!("\"{}\" is {} bytes, but the original is {}", op, n, len));
+            }
+            Vec::new()
+        }
+        ["ret"] => vec![RET],
+        ["jmp_over", n] => {
+            let n = count(n)?;
+            if n > i8::MAX as u64 {
+                return Err(format!("\"{}\": a short jump can only skip {} bytes", op, i8::MAX));
+            }
+            vec![JMP_SHORT, n as u8]
+        }
+        _ => return Err(format!("unknown patch operation \"{}\"", op))
+    };
+
+    if bytes.len() > len {
+        return Err(format!("\"{}\" takes {} bytes, but the original is only {}", op, bytes.len(), len));
+    }
+    bytes.resize(len, NOP);
+    Ok(bytes)
+}
+
+fn parse_int(word: &str) -> Option<u64> {
+    match word.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => word.parse().ok()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Definition {
+    #[serde(default)]
+    schema_version:     Option<i64>,
+    #[serde(default)]
+    name:               Option<String>,
+    pub arch:           String,
+    #[serde(default)]
+    algo:               Option<String>,
+    pub unpatched_hash: String,
+    pub patched_hash:   String,
+    #[serde(default)]
+    size:               Option<u64>,
+    #[serde(default)]
+    unpatched_code_hash: Option<String>,
+    #[serde(default)]
+    patched_code_hash:   Option<String>,
+    changes:            Vec<ChangeDef>,
+}
+
+fn leak_str(s: String) -> &'static str {
+    Box::leak(s.into_boxed_str())
+}
+
+impl Definition {
+    /// Parses and sanity-checks a definition; `origin` is only used in error messages.
+    pub fn parse(text: &str, origin: &Path) -> Result<Definition, Box<dyn Error>> {
+        let fail = |reason: String| err_box(PatcherError::BadDefinition {
+            path: origin.to_string_lossy().to_string(),
+            reason
+        });
+
+        let mut def: Definition = match toml::from_str(text) {
+            Ok(def) => def,
+            Err(e) => {
+                // a newer schema may well have fields we don't know, but that's the real problem
+                let schema_version = toml::from_str::<toml::Table>(text).ok()
+                    .and_then(|table| table.get("schema_version")?.as_integer());
+                return match schema_version {
+                    Some(version) if version > SCHEMA_VERSION => fail(newer_schema(version)),
+                    _ => fail(e.message().to_string())
+                };
+            }
+        };
+
+        match def.schema_version.unwrap_or(1) {
+            version if version > SCHEMA_VERSION => return fail(newer_schema(version)),
+            version if version < 1 => return fail(format!("there's no schema version {}", version)),
+            SCHEMA_VERSION => {}
+            // older than the current version, and so deprecated; there are none yet
+            version => note!("Warning: {} is written for schema version {}, which is deprecated; \
+                it still loads, but should say schema_version = {}",
+                origin.to_string_lossy(), version, SCHEMA_VERSION),
+        }
+
+        if def.arch.parse::<Arch>().is_err() {
+            return fail(format!("unknown arch \"{}\"", def.arch));
+        }
+
+        let algo = match def.algo() {
+            Some(algo) => algo,
+            None => return fail(format!("unknown hash algorithm \"{}\"", def.algo.as_deref().unwrap_or("")))
+        };
+        for hash in def.hashes() {
+            if hash.len() != algo.hex_len() || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                return fail(format!("\"{}\" is not a {} hash", hash, def.algo.as_deref().unwrap_or("sha1")));
+            }
+        }
+
+        let x86 = matches!(def.arch.parse(), Ok(Arch::X86 | Arch::X86_64));
+        for change in &mut def.changes {
+            change.patch = match &change.patch_def {
+                PatchDef::Bytes(bytes) => bytes.clone(),
+                // the operations are all x86 instructions
+                PatchDef::Op(op) if !x86 => {
+                    return fail(format!("patch operation \"{}\" only makes sense for x86", op));
+                }
+                PatchDef::Op(op) => match expand(op, &change.original) {
+                    Ok(bytes) => bytes,
+                    Err(reason) => return fail(format!("change at {:#x}: {}", change.offset, reason))
+                }
+            };
+        }
+
+        let changes = def.changes.iter()
+            .map(|change| (change.offset, &change.original[..], &change.patch[..]));
+        if let Err(reason) = check_changes(changes) {
+            return fail(reason);
+        }
+
+        def.unpatched_hash.make_ascii_lowercase();
+        def.patched_hash.make_ascii_lowercase();
+        for hash in def.unpatched_code_hash.iter_mut().chain(def.patched_code_hash.iter_mut()) {
+            hash.make_ascii_lowercase();
+        }
+
+        Ok(def)
+    }
+
+    /// What the hashes are taken with, or `None` if it's one we don't know.
+    fn algo(&self) -> Option<HashAlgo> {
+        match &self.algo {
+            Some(name) => HashAlgo::parse(name),
+            None => Some(HashAlgo::Sha1)
+        }
+    }
+
+    /// Every hash that identifies this build.
+    pub fn hashes(&self) -> impl Iterator<Item = &str> {
+        iter::once(&self.unpatched_hash)
+            .chain(iter::once(&self.patched_hash))
+            .chain(self.unpatched_code_hash.iter())
+            .chain(self.patched_code_hash.iter())
+            .map(String::as_str)
+    }
+
+    pub fn change_count(&self) -> usize {
+        self.changes.len()
+    }
+
+    /// Turns this into a `Version` that lives as long as the built-in ones do.
+    fn into_version(self) -> &'static Version {
+        let algo = self.algo().unwrap();
+        let changes: Vec<Change> = self.changes.into_iter()
+            .map(|change| {
+                let context = if change.before.is_empty() && change.after.is_empty() {
+                    None
+                }
+                else {
+                    Some(Context {
+                        before: Box::leak(change.before.into_boxed_slice()),
+                        after:  Box::leak(change.after.into_boxed_slice()),
+                    })
+                };
+                Change {
+                    name:        change.name.map(leak_str),
+                    description: change.description.map(leak_str),
+                    offset:      change.offset,
+                    original:    Box::leak(change.original.into_boxed_slice()),
+                    patch:       Box::leak(change.patch.into_boxed_slice()),
+                    mnemonic:    change.mnemonic.map(leak_str),
+                    context,
+                }
+            })
+            .collect();
+
+        Box::leak(Box::new(Version {
+            name:                self.name.map(leak_str),
+            arch:                self.arch.parse().unwrap(),
+            algo,
+            size:                self.size,
+            unpatched_hash:      leak_str(self.unpatched_hash),
+            patched_hash:        leak_str(self.patched_hash),
+            unpatched_code_hash: self.unpatched_code_hash.map(leak_str),
+            patched_code_hash:   self.patched_code_hash.map(leak_str),
+            changes:             Box::leak(changes.into_boxed_slice()),
+        }))
+    }
+}
+
+pub fn versions_dir() -> Result<PathBuf, Box<dyn Error>> {
+    Ok(config_dir()?.join("versions.d"))
+}
+
+/// Reads every definition in `dir`, in file name order. A missing directory just means
+/// there aren't any.
+pub fn load_dir(dir: &Path) -> Result<Vec<(PathBuf, Definition)>, Box<dyn Error>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into())
+    };
+
+    let mut paths = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "toml") {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    paths.into_iter()
+        .map(|path| {
+            let def = Definition::parse(&fs::read_to_string(&path)?, &path)?;
+            Ok((path, def))
+        })
+        .collect()
+}
+
+static KNOWN_VERSIONS: OnceLock<Vec<&'static Version>> = OnceLock::new();
+
+/// Adds the embedded definitions and those in versions.d to the known versions. Must run
+/// before anything asks for `known_versions`.
+pub fn load_known_versions() -> Result<(), Box<dyn Error>> {
+    for version in VERSIONS.iter() {
+        let changes = version.changes.iter()
+            .map(|change| (change.offset, change.original, change.patch));
+        if let Err(reason) = check_changes(changes) {
+            return err_box(PatcherError::BadVersion {
+                hash: version.unpatched_hash.to_string(),
+                reason
+            });
+        }
+    }
+
+    let mut versions: Vec<&'static Version> = VERSIONS.iter().collect();
+    let mut origins: Vec<String> = vec!["the built-in versions".to_string(); versions.len()];
+    for (path, def) in crate::embedded::load()?.into_iter().chain(load_dir(&versions_dir()?)?) {
+        versions.push(def.into_version());
+        origins.push(path.to_string_lossy().to_string());
+    }
+
+    if let Err(e) = check_unique_hashes(&versions, &origins) {
+        return err_box(e);
+    }
+
+    // only fails if something already asked for the built-ins alone, which would be a bug
+    KNOWN_VERSIONS.set(versions).map_err(|_| "known versions loaded twice")?;
+    Ok(())
+}
+
+/// Checks that no hash is shared between `versions`, each of which came from the same place in
+/// `origins`. A hash has to pick out exactly one build, or the first to match would get patched
+/// with whichever build's offsets that happens to be.
+pub fn check_unique_hashes(versions: &[&Version], origins: &[String]) -> Result<(), PatcherError> {
+    let mut seen: Vec<(&str, &str)> = Vec::new();
+    for (version, origin) in versions.iter().zip(origins) {
+        for hash in version.hashes() {
+            if let Some((_, owner)) = seen.iter().find(|(seen, _)| *seen == hash) {
+                return Err(PatcherError::DuplicateHash {
+                    hash:   hash.to_string(),
+                    first:  owner.to_string(),
+                    second: origin.clone()
+                });
+            }
+            seen.push((hash, origin));
+        }
+    }
+    Ok(())
+}
+
+/// Picks out a known build by its index in `known_versions`, its name, or any of its hashes.
+pub fn select(selector: &str) -> Result<&'static Version, Box<dyn Error>> {
+    let versions = known_versions();
+    let found = match selector.parse::<usize>() {
+        Ok(index) => versions.get(index).copied(),
+        Err(_) => versions.iter().copied().find(|version| {
+            version.name == Some(selector)
+                || version.hashes().any(|hash| hash.eq_ignore_ascii_case(selector))
+        })
+    };
+    match found {
+        Some(version) => Ok(version),
+        None => err_box(PatcherError::NoSuchVersion { selector: selector.to_string() })
+    }
+}
+
+/// The built-in versions followed by any embedded or loaded definitions.
+pub fn known_versions() -> &'static [&'static Version] {
+    KNOWN_VERSIONS.get_or_init(|| VERSIONS.iter().collect())
+}
@@ -0,0 +1,58 @@
+// An extra guardrail for offsets that are a few bytes off, which can happen to match a hash
+// when the neighbouring bytes happen to be identical: disassemble whatever sits at each
+// change's offset and compare it with the mnemonic the definition expects there.
+
+use {
+    std::{
+        error::Error,
+        io::{Read, Seek, SeekFrom},
+    },
+    iced_x86::{Decoder, DecoderOptions},
+    crate::{err_box, binary::Arch, PatcherError, Version},
+};
+
+/// Checks every change that names a mnemonic against the (unpatched) `file`. Mismatches are
+/// warnings, or errors if `strict`.
+pub fn check<F: Read + Seek>(file: &mut F, version: &Version, strict: bool)
+    -> Result<(), Box<dyn Error>>
+{
+    let bitness = match version.arch {
+        Arch::X86    => 32,
+        Arch::X86_64 => 64,
+        _ => return Ok(())
+    };
+
+    for change in version.changes {
+        let expected = match change.mnemonic {
+            Some(mnemonic) => mnemonic,
+            None => continue
+        };
+
+        // 15 bytes is as long as an x86 instruction gets
+        let mut bytes = Vec::with_capacity(15);
+        file.seek(SeekFrom::Start(change.offset))?;
+        (&mut *file).take(15).read_to_end(&mut bytes)?;
+
+        let instruction = Decoder::with_ip(bitness, &bytes, 0, DecoderOptions::NONE).decode();
+        let found = if instruction.is_invalid() {
+            "(invalid)".to_string()
+        }
+        else {
+            format!("{:?}", instruction.mnemonic()).to_lowercase()
+        };
+
+        if !found.eq_ignore_ascii_case(expected) {
+            let mismatch = PatcherError::MnemonicMismatch {
+                offset:   change.offset,
+                expected: expected.to_string(),
+                found,
+            };
+            if strict {
+                return err_box(mismatch);
+            }
+            eprintln!("Warning: {}", mismatch);
+        }
+    }
+
+    Ok(())
+}
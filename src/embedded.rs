@@ -0,0 +1,148 @@
+// Version definitions appended to the patcher's own executable, so a distribution can ship
+// new offsets without rebuilding the code. The end of the executable then looks like:
+//
+//     <blob> <SHA-1 of blob> <blob length as u64 LE> "bl-patcher defs\0"
+//
+// where the blob holds definitions (in the format described in defs.rs) as
+// `<name> 0x00 <length as u64 LE> <contents>`, one after another, the same encoding a
+// bundle signs. An executable without the trailer simply has no embedded definitions.
+
+use {
+    std::{
+        convert::TryFrom,
+        error::Error,
+        fs::{self, File},
+        io::{Read, Seek, SeekFrom},
+        path::{Path, PathBuf},
+    },
+    sha1::Sha1,
+    crate::{
+        bundle,
+        defs::Definition,
+    },
+};
+
+const MAGIC: &[u8; 16] = b"bl-patcher defs\0";
+const TRAILER_LEN: u64 = 20 + 8 + 16;
+
+fn sha1(bytes: &[u8]) -> [u8; 20] {
+    let mut hasher = Sha1::new();
+    hasher.update(bytes);
+    hasher.digest().bytes()
+}
+
+/// Splits the blob back into named entries.
+fn decode(mut blob: &[u8]) -> Option<Vec<(String, Vec<u8>)>> {
+    let mut entries = Vec::new();
+    while !blob.is_empty() {
+        let nul = blob.iter().position(|&byte| byte == 0)?;
+        let name = String::from_utf8(blob[.. nul].to_vec()).ok()?;
+        blob = &blob[nul + 1 ..];
+
+        let len = u64::from_le_bytes(<[u8; 8]>::try_from(blob.get(.. 8)?).ok()?) as usize;
+        blob = &blob[8 ..];
+        entries.push((name, blob.get(.. len)?.to_vec()));
+        blob = &blob[len ..];
+    }
+    Some(entries)
+}
+
+/// Reads the blob from the end of `file`, if there is a good one.
+fn read_blob(file: &mut File) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+    let size = file.metadata()?.len();
+    if size < TRAILER_LEN {
+        return Ok(None);
+    }
+
+    let mut trailer = [0u8; TRAILER_LEN as usize];
+    file.seek(SeekFrom::Start(size - TRAILER_LEN))?;
+    file.read_exact(&mut trailer)?;
+    if trailer[28 ..] != MAGIC[..] {
+        return Ok(None);
+    }
+
+    let len = u64::from_le_bytes(<[u8; 8]>::try_from(&trailer[20 .. 28]).unwrap());
+    if len > size - TRAILER_LEN {
+        note!("Warning: ignoring embedded definitions: bad length");
+        return Ok(None);
+    }
+
+    let mut blob = vec![0u8; len as usize];
+    file.seek(SeekFrom::Start(size - TRAILER_LEN - len))?;
+    file.read_exact(&mut blob)?;
+    if sha1(&blob)[..] != trailer[.. 20] {
+        note!("Warning: ignoring embedded definitions: checksum mismatch");
+        return Ok(None);
+    }
+
+    Ok(Some(blob))
+}
+
+/// The definitions embedded in the running executable, if any.
+pub fn load() -> Result<Vec<(PathBuf, Definition)>, Box<dyn Error>> {
+    let exe = std::env::current_exe()?;
+    let blob = match read_blob(&mut File::open(&exe)?)? {
+        Some(blob) => blob,
+        None => return Ok(Vec::new())
+    };
+
+    let entries = match decode(&blob) {
+        Some(entries) => entries,
+        None => {
+            note!("Warning: ignoring embedded definitions: malformed");
+            return Ok(Vec::new());
+        }
+    };
+
+    entries.into_iter()
+        .map(|(name, contents)| {
+            // errors name the definition as if it were a file inside the executable
+            let origin = exe.join(&name);
+            let text = String::from_utf8(contents)
+                .map_err(|_| format!("{} is not UTF-8", origin.to_string_lossy()))?;
+            Ok((origin.clone(), Definition::parse(&text, &origin)?))
+        })
+        .collect()
+}
+
+/// Writes a copy of the running executable to `output`, with `files` embedded in place of
+/// whatever definitions it had.
+pub fn embed(output: &Path, files: &[PathBuf]) -> Result<(), Box<dyn Error>> {
+    let mut entries = Vec::new();
+    for path in files {
+        let text = fs::read_to_string(path)?;
+        let def = Definition::parse(&text, path)?;
+
+        let name = path.file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .ok_or_else(|| format!("{} is not a file name", path.to_string_lossy()))?;
+        eprintln!("Embedding build {} ({}, {} changes) from {}",
+            def.unpatched_hash, def.arch, def.change_count(), path.to_string_lossy());
+        entries.push((name, text.into_bytes()));
+    }
+    entries.sort();
+
+    let mut exe = File::open(std::env::current_exe()?)?;
+    let mut image = Vec::new();
+    exe.read_to_end(&mut image)?;
+
+    // strip off any definitions embedded already
+    if let Some(blob) = read_blob(&mut exe)? {
+        image.truncate(image.len() - TRAILER_LEN as usize - blob.len());
+    }
+
+    let blob = bundle::encode_entries(&entries);
+    image.extend_from_slice(&blob);
+    image.extend_from_slice(&sha1(&blob));
+    image.extend_from_slice(&(blob.len() as u64).to_le_bytes());
+    image.extend_from_slice(MAGIC);
+
+    fs::write(output, image)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(output, fs::Permissions::from_mode(0o755))?;
+    }
+    eprintln!("Wrote {}", output.to_string_lossy());
+    Ok(())
+}
@@ -0,0 +1,134 @@
+// A timeline of what's been done to each game's executable, in <config>/history/<appid>.jsonl,
+// one event per line:
+//
+//     {"time":1571875200,"event":"patched","build":"win32-2019-06-24","hash":"bc1d...",
+//      "changes":["say-prefix","dev-commands","set-command"],"exe":"/.../Borderlands2.exe"}
+//
+// `hash` is always the build's unpatched hash (or, for an unknown build, what it hashed to
+// before patching), so a game update between two events shows up as a change of hash.
+// `history` prints it; nothing else ever reads it.
+
+use {
+    std::{
+        error::Error,
+        fs::{self, OpenOptions},
+        io::Write,
+        path::{Path, PathBuf},
+        time::{SystemTime, UNIX_EPOCH},
+    },
+    serde::{Deserialize, Serialize},
+    crate::{config_dir, defs, Change},
+};
+
+#[derive(Serialize, Deserialize)]
+struct Event {
+    // seconds since the Unix epoch
+    time:    u64,
+    // "patched", "unpatched" or "restored"
+    event:   String,
+    build:   String,
+    hash:    String,
+    #[serde(default)]
+    changes: Vec<String>,
+    exe:     PathBuf,
+}
+
+fn history_path(appid: u32) -> Result<PathBuf, Box<dyn Error>> {
+    Ok(config_dir()?.join("history").join(format!("{}.jsonl", appid)))
+}
+
+/// What to call a change in the history: its name, or failing that its offset.
+fn change_label(change: &Change) -> String {
+    match change.name {
+        Some(name) => name.to_string(),
+        None => format!("{:#x}", change.offset)
+    }
+}
+
+/// Notes that `event` happened to `exe`, which is (or was) the build with unpatched hash
+/// `hash`; `build` is what to call it, if not the name of the known build with that hash.
+/// History is only a record, so failing to write it is just a warning.
+pub fn record(appid: u32, exe: &Path, event: &str, build: Option<String>, hash: &str, changes: &[Change]) {
+    let build = build.unwrap_or_else(|| {
+        defs::known_versions().iter()
+            .find(|version| version.unpatched_hash == hash)
+            .map_or(hash, |version| version.label())
+            .to_string()
+    });
+    let event = Event {
+        time:    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs()),
+        event:   event.to_string(),
+        build,
+        hash:    hash.to_string(),
+        changes: changes.iter().map(change_label).collect(),
+        exe:     exe.to_path_buf(),
+    };
+
+    let result: Result<(), Box<dyn Error>> = (|| {
+        let path = history_path(appid)?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut line = serde_json::to_string(&event)?;
+        line.push('\n');
+        OpenOptions::new().create(true).append(true).open(path)?.write_all(line.as_bytes())?;
+        Ok(())
+    })();
+    if let Err(e) = result {
+        eprintln!("Warning: couldn't record this in the history: {}", e);
+    }
+}
+
+/// `time` as "YYYY-MM-DD HH:MM:SS UTC".
+fn format_time(time: u64) -> String {
+    // days since the epoch to a civil date; see Howard Hinnant's `civil_from_days`
+    let days = (time / 86400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    let seconds = time % 86400;
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year, month, day, seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+/// Prints the history of `appid`'s executable, oldest first.
+pub fn print(appid: u32) -> Result<(), Box<dyn Error>> {
+    let path = history_path(appid)?;
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => {
+            eprintln!("Nothing recorded yet in {}", path.to_string_lossy());
+            return Ok(());
+        }
+        Err(e) => return Err(e.into())
+    };
+
+    let mut last_hash: Option<String> = None;
+    for (index, line) in text.lines().enumerate() {
+        let event: Event = match serde_json::from_str(line) {
+            Ok(event) => event,
+            Err(_) => {
+                eprintln!("Warning: skipping unreadable line {} of {}", index + 1, path.to_string_lossy());
+                continue;
+            }
+        };
+
+        // the exe can only have become another build behind our back, or by a revert
+        if let Some(last_hash) = &last_hash {
+            if *last_hash != event.hash && event.event != "restored" {
+                println!("{:23}  (the game was updated: now build {})", "", event.build);
+            }
+        }
+
+        let changes = if event.changes.is_empty() { String::new() } else { format!(": {}", event.changes.join(", ")) };
+        println!("{}  {} build {}{}", format_time(event.time), event.event, event.build, changes);
+        last_hash = Some(event.hash);
+    }
+    Ok(())
+}
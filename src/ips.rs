@@ -0,0 +1,68 @@
+// The detected build's changes as an IPS patch against its unpatched executable:
+//
+//     "PATCH"  { <offset, 3 bytes BE> <length, 2 bytes BE> <bytes> }...  "EOF"
+//
+// IPS can't address anything past 16 MiB, which most of BL2's changes are, so those can't be
+// exported this way at all.
+
+use {
+    std::{
+        error::Error,
+        fs::{self, File},
+        io::{Read, Seek, SeekFrom},
+        path::Path,
+    },
+    crate::{err_box, PatcherError, Version},
+};
+
+const MAX_OFFSET: u64 = 0xff_ffff;
+
+// a record at this offset would read as the end marker
+const EOF_OFFSET: u64 = 0x45_4f46;
+
+/// What the byte at `offset` should be once patched: the patch's, if a change covers it,
+/// otherwise whatever is there in `exe`.
+fn patched_byte(version: &Version, exe: &mut File, offset: u64) -> Result<u8, Box<dyn Error>> {
+    for change in version.changes {
+        if (change.offset .. change.offset + change.patch.len() as u64).contains(&offset) {
+            return Ok(change.patch[(offset - change.offset) as usize]);
+        }
+    }
+
+    let mut byte = [0u8];
+    exe.seek(SeekFrom::Start(offset))?;
+    exe.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+/// Encodes `version`'s changes; `exe` is a copy of that build, patched or not.
+pub fn encode(version: &Version, exe: &mut File) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut changes: Vec<_> = version.changes.iter().collect();
+    changes.sort_by_key(|change| change.offset);
+
+    let mut ips = b"PATCH".to_vec();
+    for change in changes {
+        let (mut offset, mut bytes) = (change.offset, change.patch.to_vec());
+        // start a byte early, writing that byte as it will be once patched anyway
+        if offset == EOF_OFFSET {
+            offset -= 1;
+            bytes.insert(0, patched_byte(version, exe, offset)?);
+        }
+
+        if offset + bytes.len() as u64 - 1 > MAX_OFFSET {
+            return err_box(PatcherError::IpsOutOfRange { offset: change.offset });
+        }
+        ips.extend_from_slice(&offset.to_be_bytes()[5 ..]);
+        ips.extend_from_slice(&(bytes.len() as u16).to_be_bytes());
+        ips.extend_from_slice(&bytes);
+    }
+    ips.extend_from_slice(b"EOF");
+    Ok(ips)
+}
+
+pub fn export(version: &Version, exe: &mut File, path: &Path) -> Result<(), Box<dyn Error>> {
+    fs::write(path, encode(version, exe)?)?;
+    eprintln!("Wrote {} ({} changes to build {})",
+        path.to_string_lossy(), version.changes.len(), version.label());
+    Ok(())
+}
@@ -1,15 +1,53 @@
+// eprintln!, except under --quiet-status, which prints nothing at all; for the notes and
+// warnings that can come up before the state is known. Defined ahead of the modules so they
+// can use it too.
+macro_rules! note {
+    ($($arg:tt)*) => {
+        if !crate::QUIET.load(std::sync::atomic::Ordering::Relaxed) {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+mod backup;
+mod binary;
+mod bundle;
+mod cache;
+mod config;
+mod defs;
+#[cfg(feature = "disasm")]
+mod disasm;
+mod embedded;
+mod history;
+mod ips;
+#[cfg(feature = "mmap")]
+mod mmap;
+mod relocate;
+mod script;
+#[cfg(all(feature = "serve", unix))]
+mod serve;
+mod shortcuts;
 
 use {
     std::{
         error::Error,
         fs::{self, File, OpenOptions},
         fmt::{self, Display, Formatter},
-        io::{self, BufReader, BufRead, Seek, Write, SeekFrom},
+        io::{self, BufReader, BufRead, Cursor, Read, Seek, Write, SeekFrom},
+        iter,
         path::{Path, PathBuf},
         result::Result,
+        slice,
         str::FromStr,
+        sync::{Arc, Mutex, OnceLock, atomic::{AtomicBool, AtomicU64, Ordering}},
     },
+    crate::{
+        backup::{Backup, BackupKind},
+        binary::Arch,
+    },
+    clap::{error::ErrorKind, CommandFactory, Parser, Subcommand, ValueEnum},
     regex::Regex,
+    serde_json::json,
     sha1::{Digest, Sha1},
     steamy_vdf as vdf,
 };
@@ -18,24 +56,128 @@ fn err_box<T, E: Error + 'static>(e: E) -> Result<T, Box<dyn Error>> {
     Err(Box::new(e))
 }
 
+fn parse_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0 .. hex.len()).step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i .. i + 2)?, 16).ok())
+        .collect()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Standard base64, with padding.
+fn to_base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut text = String::new();
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate()
+            .fold(0u32, |group, (index, &byte)| group | (byte as u32) << (16 - 8 * index));
+        for index in 0 .. 4 {
+            if index <= chunk.len() {
+                text.push(ALPHABET[(group >> (18 - 6 * index) & 0x3f) as usize] as char);
+            }
+            else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+/// A signed offset difference, like "+0x40" or "-0x40".
+fn signed_hex(delta: i64) -> String {
+    let sign = if delta < 0 { '-' } else { '+' };
+    format!("{}{:#x}", sign, delta.unsigned_abs())
+}
+
+/// Where our own settings and data live: $XDG_CONFIG_HOME/bl-patcher or
+/// ~/.config/bl-patcher, or %APPDATA%\bl-patcher on Windows.
+fn config_dir() -> Result<PathBuf, Box<dyn Error>> {
+    if cfg!(windows) {
+        return Ok(PathBuf::from(std::env::var("APPDATA")?).join("bl-patcher"));
+    }
+
+    match std::env::var("XDG_CONFIG_HOME") {
+        Ok(dir) if !dir.is_empty() => Ok(PathBuf::from(dir).join("bl-patcher")),
+        _ => {
+            let home: PathBuf = std::env::var("HOME")?.into();
+            Ok(home.join(".config/bl-patcher"))
+        }
+    }
+}
+
 struct Change {
-    offset:   u64,
-    original: &'static [u8],
-    patch:    &'static [u8],
+    // for matching up the same change across builds
+    name:        Option<&'static str>,
+    // what it does for the player, as in "dev commands enabled"
+    description: Option<&'static str>,
+    offset:      u64,
+    original:    &'static [u8],
+    patch:       &'static [u8],
+    // the instruction that should start at `offset` before patching, if it's code at all
+    #[cfg_attr(not(feature = "disasm"), allow(dead_code))]
+    mnemonic:    Option<&'static str>,
+    // bytes around `original`, for finding it again in a shifted build; see relocate.rs
+    context:     Option<Context>,
+}
+
+struct Context {
+    before: &'static [u8],
+    after:  &'static [u8],
+}
+
+impl Change {
+    /// For filling in the optional fields of a change with struct update syntax.
+    const BARE: Change = Change {
+        name: None, description: None, offset: 0, original: &[], patch: &[], mnemonic: None,
+        context: None
+    };
 }
 
 struct Version {
-    unpatched_hash: &'static str,
-    patched_hash:   &'static str,
-    changes:        &'static [Change],
+    name:                Option<&'static str>,
+    arch:                Arch,
+    // what all of the hashes below are taken with
+    algo:                HashAlgo,
+    // of the unpatched file
+    size:                Option<u64>,
+    unpatched_hash:      &'static str,
+    patched_hash:        &'static str,
+    // hashes of just the PE sections, which survive re-signing; see `binary::pe_section_ranges`
+    unpatched_code_hash: Option<&'static str>,
+    patched_code_hash:   Option<&'static str>,
+    changes:             &'static [Change],
 }
 
+#[derive(Clone, Copy, Debug)]
 enum Action {
     Apply, Undo
 }
 
 impl Version {
-    fn modify_file(&self, action: Action, file: &mut File) -> Result<(), io::Error> {
+    /// What to call this build in messages.
+    fn label(&self) -> &'static str {
+        self.name.unwrap_or(self.unpatched_hash)
+    }
+
+    /// Every hash that identifies this build.
+    fn hashes(&self) -> impl Iterator<Item = &'static str> {
+        iter::once(self.unpatched_hash)
+            .chain(iter::once(self.patched_hash))
+            .chain(self.unpatched_code_hash)
+            .chain(self.patched_code_hash)
+    }
+
+    /// With `read_back`, each change is read back as soon as it's written, so a write that
+    /// went nowhere fails there and then, at its offset, rather than only at the final verify.
+    fn modify_file<F: Read + Write + Seek>(&self, action: Action, file: &mut F, read_back: bool)
+        -> Result<(), io::Error>
+    {
         for change in self.changes {
             file.seek(SeekFrom::Start(change.offset))?;
             let bytes = match action {
@@ -43,22 +185,72 @@ impl Version {
                 Action::Undo  => &change.original
             };
             file.write_all(bytes)?;
+
+            if read_back {
+                let mut written = vec![0u8; bytes.len()];
+                file.seek(SeekFrom::Start(change.offset))?;
+                file.read_exact(&mut written)?;
+                if written != *bytes {
+                    return Err(io::Error::other(PatcherError::Unwritten { offset: change.offset }));
+                }
+            }
         }
         Ok(())
     }
 }
 
+/// Undo only exactly reverses Apply (for any file holding the original bytes) if each change
+/// swaps bytes one-for-one and no two changes touch the same byte. Takes changes as
+/// (offset, original, patch).
+fn check_changes<'a, I>(changes: I) -> Result<(), String>
+    where I: Iterator<Item = (u64, &'a [u8], &'a [u8])>
+{
+    let mut changes: Vec<_> = changes.collect();
+    changes.sort_by_key(|&(offset, _, _)| offset);
+
+    for &(offset, original, patch) in &changes {
+        if original.is_empty() || original.len() != patch.len() {
+            return Err(format!("change at {:#x} must replace bytes one-for-one", offset));
+        }
+    }
+
+    for pair in changes.windows(2) {
+        let (offset, original, _) = pair[0];
+        if offset + original.len() as u64 > pair[1].0 {
+            return Err(format!("changes at {:#x} and {:#x} overlap", offset, pair[1].0));
+        }
+    }
+
+    Ok(())
+}
+
 static VERSIONS: [Version; 1] = [
     Version { // win32, with cl:ffs, as of 2019-06-24
-        unpatched_hash: "bc1d695c6fdb3dea491b367f73bbb045c316b32e",
-        patched_hash:   "fc8afce04782532b0fe7a70a80ee1070da858e32",
-        changes:        &[
+        name:                Some("win32-2019-06-24"),
+        arch:                Arch::X86,
+        algo:                HashAlgo::Sha1,
+        size:                None,
+        unpatched_hash:      "bc1d695c6fdb3dea491b367f73bbb045c316b32e",
+        patched_hash:        "fc8afce04782532b0fe7a70a80ee1070da858e32",
+        // not recorded yet, like the size
+        unpatched_code_hash: None,
+        patched_code_hash:   None,
+        changes:             &[
             // remove the "say" string prefixed to console entries
-            Change { offset: 0x012f_8b90, original: &[0x73], patch: &[0x00] },
+            Change {
+                name: Some("say-prefix"), description: Some("console 'say' prefix removed"),
+                offset: 0x012f_8b90, original: &[0x73], patch: &[0x00], ..Change::BARE
+            },
             // enable dev commands
-            Change { offset: 0x0169_9cb2, original: &[0xb8], patch: &[0xb7] },
+            Change {
+                name: Some("dev-commands"), description: Some("dev commands enabled"),
+                offset: 0x0169_9cb2, original: &[0xb8], patch: &[0xb7], ..Change::BARE
+            },
             // enable 'set'
-            Change { offset: 0x0042_d740, original: &[0xc0], patch: &[0xff] },
+            Change {
+                name: Some("set-command"), description: Some("'set' command enabled"),
+                offset: 0x0042_d740, original: &[0xc0], patch: &[0xff], ..Change::BARE
+            },
         ]
     },
 ];
@@ -67,7 +259,47 @@ static VERSIONS: [Version; 1] = [
 enum PatcherError {
     UnknownVersion { hash: Digest },
     BadVDF { path: String },
-    CantFindManifest { appid: u32 },
+    NoSteamRoot,
+    CantFindManifest { appid: u32, other_roots: Vec<PathBuf> },
+    IpsOutOfRange { offset: u64 },
+    // (Steam root, exe) for each
+    AmbiguousInstall { installs: Vec<(PathBuf, PathBuf)> },
+    DiscoveryTimeout { seconds: u64 },
+    ArchMismatch { expected: Arch, found: Arch },
+    UnexpectedHash { expected: String, found: String },
+    // the candidates --arch ruled out, and what each is built for
+    NoExeForArch { arch: Arch, found: Vec<(PathBuf, Arch)> },
+    BadBackup { path: String },
+    ReadOnly { path: String },
+    // (needed, available) in bytes, if found out before writing rather than by failing to
+    InsufficientSpace { path: String, space: Option<(u64, u64)> },
+    ReadOnlyMode,
+    NotAFile { path: String, target: String },
+    SystemPath { path: String, dir: &'static str },
+    HardLinked { path: String, links: u64 },
+    NoBackup { exe: String },
+    AmbiguousBackup { hashes: Vec<String> },
+    NoBuildBackup { hash: String, stored: Vec<String> },
+    BackupMismatch { path: String, expected: String, found: String },
+    BadVersion { hash: String, reason: String },
+    BadDefinition { path: String, reason: String },
+    BadConfig { path: String, reason: String },
+    BadBundle { path: String, reason: String },
+    NoTrustedKeys { path: String },
+    BadTrustedKey { path: String, key: String },
+    UntrustedBundle { path: String },
+    HashCollision { hash: String, owner: String },
+    DuplicateHash { hash: String, first: String, second: String },
+    NoSuchVersion { selector: String },
+    Unverified { action: Action, expected: String, found: String },
+    VerifyUnknown { expected: String, found: String },
+    NoCodeHash { build: &'static str, action: Action },
+    // how many known builds have the bytes assumed; see `assume_state`
+    AssumedState { patched: bool, builds: usize },
+    Unwritten { offset: u64 },
+    CantRelocate { hash: String, reasons: Vec<String> },
+    #[cfg(feature = "disasm")]
+    MnemonicMismatch { offset: u64, expected: String, found: String },
 }
 
 impl Display for PatcherError {
@@ -79,8 +311,165 @@ impl Display for PatcherError {
             PatcherError::BadVDF { path } => {
                 write!(f, "Invalid VDF file: {}", path)
             }
-            PatcherError::CantFindManifest { appid } => {
-                write!(f, "Cannot find manifest for appid {}", appid)
+            PatcherError::NoSteamRoot => {
+                write!(f, "Cannot find a Steam installation")
+            }
+            PatcherError::CantFindManifest { appid, other_roots } => {
+                write!(f, "Cannot find manifest for appid {}", appid)?;
+                for root in other_roots {
+                    write!(f, "\nAnother Steam installation exists at {0}; try --steam-root {0}",
+                        root.to_string_lossy())?;
+                }
+                Ok(())
+            }
+            PatcherError::AmbiguousInstall { installs } => {
+                write!(f, "The game is installed in more than one place; pick one with --steam-root:")?;
+                for (root, exe) in installs {
+                    write!(f, "\n    {}\n        --steam-root {}", exe.to_string_lossy(), root.to_string_lossy())?;
+                }
+                Ok(())
+            }
+            PatcherError::IpsOutOfRange { offset } => {
+                write!(f, "The change at {:#x} is beyond IPS's 16 MiB limit; use a format without \
+                    one, such as BPS, or take the changes from --diff-json", offset)
+            }
+            PatcherError::DiscoveryTimeout { seconds } => {
+                write!(f, "Searching Steam for the game took over {}s; point at it directly with \
+                    --install-path DIR or --file EXE", seconds)
+            }
+            PatcherError::ArchMismatch { expected, found } => {
+                write!(f, "Executable architecture is {}, but the patch is for {}", found, expected)
+            }
+            PatcherError::UnexpectedHash { expected, found } => {
+                write!(f, "The executable hashes as {}, not the expected {}; nothing was done", found, expected)
+            }
+            PatcherError::NoExeForArch { arch, found } => {
+                write!(f, "No {} executable found; --arch ruled out:", arch)?;
+                for (path, found) in found {
+                    write!(f, "\n    {} ({})", path.to_string_lossy(), found)?;
+                }
+                Ok(())
+            }
+            PatcherError::ReadOnly { path } => {
+                write!(f, "{} is marked read-only; clear that, or pass --clear-readonly to have it \
+                    cleared while patching and put back afterwards", path)
+            }
+            PatcherError::ReadOnlyMode => {
+                write!(f, "Refusing to write anything in read-only mode (--read-only or \
+                    BL_PATCHER_READONLY=1)")
+            }
+            PatcherError::NotAFile { path, target } => {
+                write!(f, "{} leads to {}, which is not a file that could be patched", path, target)
+            }
+            PatcherError::SystemPath { path, dir } => {
+                write!(f, "{} is under {}, a system directory, and no game belongs there; pass --force \
+                    if you really mean it", path, dir)
+            }
+            PatcherError::HardLinked { path, links } => {
+                write!(f, "{} has {} names (hard links), so patching it in place would change every \
+                    one of them; give it a copy of its own first", path, links)
+            }
+            PatcherError::InsufficientSpace { path, space: None } => {
+                write!(f, "Ran out of disk space writing {}", path)
+            }
+            PatcherError::InsufficientSpace { path, space: Some((needed, available)) } => {
+                write!(f, "Not enough disk space to write {}: it needs {} MiB, counting the \
+                    --min-free-space margin, but only {} MiB is free", path, needed / MIB, available / MIB)
+            }
+            PatcherError::BadBackup { path } => {
+                write!(f, "Invalid backup file: {}", path)
+            }
+            PatcherError::NoBackup { exe } => {
+                write!(f, "No backup found for {}", exe)
+            }
+            PatcherError::AmbiguousBackup { hashes } => {
+                write!(f, "Backups of several builds exist, so it's unclear which to restore: {}\n\
+                           Pick one with --revert-to-build <hash>", hashes.join(", "))
+            }
+            PatcherError::NoBuildBackup { hash, stored } => {
+                write!(f, "No full backup of build {} is stored", hash)?;
+                if !stored.is_empty() {
+                    write!(f, "; there are backups of: {}", stored.join(", "))?;
+                }
+                Ok(())
+            }
+            PatcherError::BackupMismatch { path, expected, found } => {
+                write!(f, "Restoring from {} should give SHA1 {}, but gave {}", path, expected, found)
+            }
+            PatcherError::BadVersion { hash, reason } => {
+                write!(f, "Invalid built-in version {}: {}", hash, reason)
+            }
+            PatcherError::BadDefinition { path, reason } => {
+                write!(f, "Invalid version definition {}: {}", path, reason)
+            }
+            PatcherError::BadConfig { path, reason } => {
+                write!(f, "Invalid config file {}: {}", path, reason)
+            }
+            PatcherError::BadBundle { path, reason } => {
+                write!(f, "Invalid patch bundle {}: {}", path, reason)
+            }
+            PatcherError::NoTrustedKeys { path } => {
+                write!(f, "No trusted keys to check bundle signatures with; add some to {}", path)
+            }
+            PatcherError::BadTrustedKey { path, key } => {
+                write!(f, "Invalid trusted key in {}: {}", path, key)
+            }
+            PatcherError::UntrustedBundle { path } => {
+                write!(f, "Patch bundle {} is not signed by a trusted key", path)
+            }
+            PatcherError::HashCollision { hash, owner } => {
+                write!(f, "SHA1 {} is already used by {}", hash, owner)
+            }
+            PatcherError::Unverified { action: Action::Apply, expected, found } => {
+                write!(f, "Patched, but the executable hashes as {} rather than {}", found, expected)
+            }
+            PatcherError::Unverified { action: Action::Undo, expected, found } => {
+                write!(f, "Unpatched, but the executable hashes as {} rather than stock build {}, \
+                    so it has other modifications and isn't back to stock; verify the game files \
+                    in Steam to get a stock copy", found, expected)
+            }
+            PatcherError::AssumedState { patched, builds: 0 } => {
+                let state = if *patched { "patched" } else { "unpatched" };
+                write!(f, "The executable doesn't have the {} bytes of any known build, so it isn't \
+                    what --assume-{} says", state, state)
+            }
+            PatcherError::AssumedState { builds, .. } => {
+                write!(f, "The executable's bytes fit {} known builds, which only hashing it can tell \
+                    apart; drop --assume-*", builds)
+            }
+            PatcherError::Unwritten { offset } => {
+                write!(f, "The change at {:#x} doesn't read back as written", offset)
+            }
+            PatcherError::NoCodeHash { build, action } => {
+                let state = match action { Action::Apply => "patched", Action::Undo => "unpatched" };
+                write!(f, "--fix-checksum changes the file's hash, and build {} has no {} code hash \
+                    recorded to verify the result by instead", build, state)
+            }
+            PatcherError::VerifyUnknown { expected, found } => {
+                write!(f, "Patched, but the executable hashes as {}, which is no known build, rather \
+                    than {}; something else may have changed it meanwhile, or the patch's offsets are \
+                    wrong", found, expected)
+            }
+            PatcherError::DuplicateHash { hash, first, second } => {
+                write!(f, "SHA1 {} identifies builds in both {} and {}", hash, first, second)
+            }
+            PatcherError::NoSuchVersion { selector } => {
+                write!(f, "No known build is called {}; known builds are:", selector)?;
+                for (index, version) in defs::known_versions().iter().enumerate() {
+                    write!(f, "\n    {}: {} ({})", index, version.label(), version.unpatched_hash)?;
+                }
+                Ok(())
+            }
+            PatcherError::CantRelocate { hash, reasons } => {
+                write!(f, "Cannot find where the patch goes in unknown build {}", hash)?;
+                for reason in reasons {
+                    write!(f, "\n    {}", reason)?;
+                }
+                Ok(())
+            }
+            #[cfg(feature = "disasm")]
+            PatcherError::MnemonicMismatch { offset, expected, found } => {
+                write!(f, "Expected {} at {:#x}, but found {}", expected, offset, found)
             }
         }
     }
@@ -88,44 +477,245 @@ impl Display for PatcherError {
 
 impl Error for PatcherError { }
 
+fn is_unknown_version(e: &(dyn Error + 'static)) -> bool {
+    matches!(e.downcast_ref(), Some(PatcherError::UnknownVersion { .. }))
+}
+
+/// A write to `path` that failed partway for want of space, as `InsufficientSpace`; any other
+/// error as it is.
+fn write_error(e: io::Error, path: &Path) -> Box<dyn Error> {
+    match e.kind() {
+        io::ErrorKind::StorageFull | io::ErrorKind::WriteZero => {
+            Box::new(PatcherError::InsufficientSpace { path: path.to_string_lossy().to_string(), space: None })
+        }
+        _ => e.into()
+    }
+}
+
+const MIB: u64 = 1 << 20;
+
+// set for the whole run by --min-free-space; see `check_free_space`
+static MIN_FREE_SPACE: AtomicU64 = AtomicU64::new(0);
+
+/// Fails, before anything is written, unless writing `size` bytes to `path` would still
+/// leave the --min-free-space margin free on its filesystem. Everything that writes a whole
+/// copy of the exe (a full backup, a restore, --output) asks first, so a full disk doesn't
+/// fail it partway. A filesystem that can't say how much room it has is let be.
+fn check_free_space(path: &Path, size: u64) -> Result<(), Box<dyn Error>> {
+    check_space(path, size, MIN_FREE_SPACE.load(Ordering::Relaxed))
+}
+
+/// `check_free_space` without the margin, for putting right an exe a failed patch left
+/// half-written: that's worth the last of the disk, where taking a new copy isn't.
+fn check_room(path: &Path, size: u64) -> Result<(), Box<dyn Error>> {
+    check_space(path, size, 0)
+}
+
+fn check_space(path: &Path, size: u64, margin: u64) -> Result<(), Box<dyn Error>> {
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let available = match fs2::available_space(dir) {
+        Ok(available) => available,
+        Err(_) => return Ok(())
+    };
+    let needed = size.saturating_add(margin);
+    if available < needed {
+        return err_box(PatcherError::InsufficientSpace {
+            path:  path.to_string_lossy().to_string(),
+            space: Some((needed, available))
+        });
+    }
+    Ok(())
+}
+
 struct ExeState {
     version: &'static Version,
     patched: bool,
+    // taken on trust from --assume-*, rather than found by hashing
+    assumed: bool,
 }
 
-fn get_exe_state(file: &mut File) -> Result<ExeState, Box<dyn Error>> {
+fn hash_file<F: Read + Seek>(file: &mut F) -> Result<Digest, io::Error> {
     file.seek(SeekFrom::Start(0))?;
 
-    // compute the SHA-1
-    let file_hash = {
-        let mut buf = BufReader::with_capacity(0x10000, file);
-        let mut hasher = Sha1::new();
+    let mut buf = BufReader::with_capacity(0x10000, file);
+    let mut hasher = Sha1::new();
+
+    loop {
+        let len = match buf.fill_buf()? {
+            slice if !slice.is_empty() => {
+                hasher.update(slice);
+                slice.len()
+            }
+            _ => { break; }
+        };
+        buf.consume(len);
+    }
+
+    Ok(hasher.digest())
+}
+
+/// Hashes only the PE section data, or returns `None` if this isn't a PE file.
+fn hash_code<F: Read + Seek>(file: &mut F) -> Result<Option<Digest>, io::Error> {
+    let ranges = match binary::pe_section_ranges(file)? {
+        Some(ranges) => ranges,
+        None => return Ok(None)
+    };
 
+    let mut hasher = Sha1::new();
+    let mut buf = vec![0u8; 0x10000];
+    for range in ranges {
+        file.seek(SeekFrom::Start(range.start))?;
+        let mut section = (&mut *file).take(range.len);
         loop {
-            let len = match buf.fill_buf().expect("I/O Error") {
-                slice if !slice.is_empty() => {
-                    hasher.update(slice);
-                    slice.len()
-                }
-                _ => { break; }
-            };
-            buf.consume(len);
+            match section.read(&mut buf)? {
+                0 => break,
+                len => hasher.update(&buf[.. len])
+            }
+        }
+    }
+
+    Ok(Some(hasher.digest()))
+}
+
+/// What a build's hashes are taken with. Everything is SHA-1 so far; a new algorithm gets a
+/// variant here, and builds that don't say which they use go on meaning SHA-1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum HashAlgo {
+    Sha1
+}
+
+impl HashAlgo {
+    /// As written in a definition's `algo`.
+    fn parse(name: &str) -> Option<HashAlgo> {
+        match name {
+            "sha1" => Some(HashAlgo::Sha1),
+            _ => None
+        }
+    }
+
+    /// How many hex digits one of its hashes has.
+    fn hex_len(self) -> usize {
+        match self {
+            HashAlgo::Sha1 => 40,
+        }
+    }
+
+    fn hash_file<F: Read + Seek>(self, file: &mut F) -> Result<String, io::Error> {
+        match self {
+            HashAlgo::Sha1 => Ok(hash_file(file)?.to_string()),
+        }
+    }
+
+    fn hash_code<F: Read + Seek>(self, file: &mut F) -> Result<Option<String>, io::Error> {
+        match self {
+            HashAlgo::Sha1 => Ok(hash_code(file)?.map(|digest| digest.to_string())),
+        }
+    }
+}
+
+/// A file's hashes under one algorithm.
+struct FileHashes {
+    algo: HashAlgo,
+    file: String,
+    code: Option<String>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum HashScope {
+    /// Identify builds by the hash of the whole file
+    File,
+    /// Also accept builds whose section data matches, even if the signature has changed
+    Code,
+}
+
+/// Checks that after `action`, the file is exactly the build `before` said it was, now
+/// patched or unpatched as appropriate.
+fn verify_state<F: Read + Seek>(file: &mut F, before: &ExeState, action: Action, scope: HashScope)
+    -> Result<(), Box<dyn Error>>
+{
+    let (expected, patched) = match action {
+        Action::Apply => (before.version.patched_hash, true),
+        Action::Undo  => (before.version.unpatched_hash, false),
+    };
+
+    match get_exe_state(file, scope) {
+        Ok(after) if std::ptr::eq(after.version, before.version) && after.patched == patched => Ok(()),
+        Ok(_) => err_box(PatcherError::Unverified {
+            action, expected: expected.to_string(), found: hash_file(file)?.to_string()
+        }),
+        Err(e) if is_unknown_version(&*e) => {
+            let (expected, found) = (expected.to_string(), hash_file(file)?.to_string());
+            match action {
+                // patching a known build into no build at all is the worst way this can go
+                Action::Apply => err_box(PatcherError::VerifyUnknown { expected, found }),
+                Action::Undo  => err_box(PatcherError::Unverified { action, expected, found }),
+            }
         }
+        Err(e) => Err(e)
+    }
+}
+
+fn get_exe_state<F: Read + Seek>(file: &mut F, scope: HashScope)
+    -> Result<ExeState, Box<dyn Error>>
+{
+    // hash once with each algorithm some known build uses
+    let mut algos: Vec<HashAlgo> = defs::known_versions().iter().map(|version| version.algo).collect();
+    algos.sort();
+    algos.dedup();
+    let mut hashes = Vec::new();
+    for algo in algos {
+        let file_hash = algo.hash_file(file)?;
+        let code_hash = match scope {
+            HashScope::File => None,
+            HashScope::Code => algo.hash_code(file)?,
+        };
+        hashes.push(FileHashes { algo, file: file_hash, code: code_hash });
+    }
 
-        hasher.digest()
+    let matches = |hash: Option<&String>, known: Option<&str>| {
+        hash.is_some_and(|hash| known == Some(hash.as_str()))
     };
 
     // check against known versions
-    for version in VERSIONS.iter() {
-        if file_hash == Digest::from_str(version.unpatched_hash).unwrap() {
-            return Ok(ExeState { version, patched: false });
-        }
-        else if file_hash == Digest::from_str(version.patched_hash).unwrap() {
-            return Ok(ExeState { version, patched: true });
+    let state = defs::known_versions().iter()
+        .find_map(|&version| {
+            let hashes = hashes.iter().find(|hashes| hashes.algo == version.algo)?;
+            if matches(Some(&hashes.file), Some(version.unpatched_hash)) {
+                Some(ExeState { version, patched: false, assumed: false })
+            }
+            else if matches(Some(&hashes.file), Some(version.patched_hash)) {
+                Some(ExeState { version, patched: true, assumed: false })
+            }
+            else if matches(hashes.code.as_ref(), version.unpatched_code_hash) {
+                Some(ExeState { version, patched: false, assumed: false })
+            }
+            else if matches(hashes.code.as_ref(), version.patched_code_hash) {
+                Some(ExeState { version, patched: true, assumed: false })
+            }
+            else {
+                None
+            }
+        });
+
+    let state = match state {
+        Some(state) => state,
+        None => {
+            // unknown builds are always reported by their SHA-1, which is what backups go by
+            let hash = match hashes.iter().find(|hashes| hashes.algo == HashAlgo::Sha1) {
+                Some(hashes) => Digest::from_str(&hashes.file).unwrap(),
+                None => hash_file(file)?
+            };
+            return err_box(PatcherError::UnknownVersion { hash });
         }
+    };
+
+    // the offsets are meaningless in a binary built for some other machine
+    let found = binary::read_arch(file)?;
+    if found != state.version.arch {
+        return err_box(PatcherError::ArchMismatch { expected: state.version.arch, found });
     }
 
-    err_box(PatcherError::UnknownVersion { hash: file_hash })
+    Ok(state)
 }
 
 fn load_libraries_vdf(path: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
@@ -141,21 +731,24 @@ fn load_libraries_vdf(path: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
         _ => return fail()
     };
 
-    let paths = entries
+    let mut libraries: Vec<(u32, PathBuf)> = entries
         .iter()
-        .filter_map(|(k, v)| -> Option<PathBuf> {
-            let _index: u32 = k.parse().ok()?;
+        .filter_map(|(k, v)| -> Option<(u32, PathBuf)> {
+            let index: u32 = k.parse().ok()?;
             match v {
                 vdf::Entry::Value(path) => {
                     let path = PathBuf::from(path.to_string()).canonicalize().ok()?;
-                    Some(path)
+                    Some((index, path))
                 }
                 _ => None
             }
         })
         .collect();
 
-    Ok(paths)
+    // the table has no order of its own, but Steam numbers the libraries in the order it uses
+    // them, and "10" comes after "9"
+    libraries.sort_by_key(|(index, _)| *index);
+    Ok(libraries.into_iter().map(|(_, path)| path).collect())
 }
 
 fn get_install_dir_from_manifest(manifest_path: &Path) -> Result<PathBuf, Box<dyn Error>> {
@@ -178,11 +771,24 @@ fn get_install_dir_from_manifest(manifest_path: &Path) -> Result<PathBuf, Box<dy
         None => return fail()
     };
 
-    let path = match captures.get(1) {
-        Some(group) => PathBuf::from("common").join(group.as_str()),
+    let install_dir = match captures.get(1) {
+        Some(group) => group.as_str(),
         None => return fail()
     };
 
+    // a manifest written on Windows may use backslashes (escaped, so doubled, in VDF); either
+    // kind separates components, and nothing may climb out of common/
+    let mut path = PathBuf::from("common");
+    for component in install_dir.split(['/', '\\']).filter(|c| !c.is_empty() && *c != ".") {
+        if component == ".." {
+            return fail();
+        }
+        path.push(component);
+    }
+    if path.components().count() == 1 {
+        return fail();
+    }
+
     Ok(path)
 
     // TODO: fix steamy_vdf so it can deal with manifests
@@ -203,85 +809,2321 @@ fn get_install_dir_from_manifest(manifest_path: &Path) -> Result<PathBuf, Box<dy
     //  }
 }
 
-fn find_install_path(appid: u32) -> Result<PathBuf, Box<dyn Error>> {
-    // find library folders
-    let home: PathBuf = std::env::var("HOME")?.into();
-    let home_steam = home.join(".steam/steam").canonicalize()?;
-    let home_steamapps = home_steam.join("steamapps");
+/// Where Borderlands 2 lives on disk, relative to the Steam library that hosts it.
+#[derive(Clone)]
+struct InstallLayout {
+    library_root: PathBuf,
+    steamapps:    PathBuf,
+    install_dir:  PathBuf,
+    exe_path:     PathBuf,
+}
 
-    let libraries_file_path = home_steamapps.join("libraryfolders.vdf");
-    let library_paths = {
-        let mut paths = load_libraries_vdf(&libraries_file_path)?;
-        paths.push(home_steam);
-        paths
-    };
-    //  eprintln!("Steam library paths:");
-    //  for path in &library_paths { eprintln!("    {}", path.to_string_lossy()); }
+/// Borderlands 2's, on Steam.
+const APPID: u32 = 49520;
 
-    // find and open the relevant manifest
-    let manifest_filename = format!("appmanifest_{}.acf", appid);
+const EXE_SUBPATH: &str = "Binaries/Win32/Borderlands2.exe";
 
-    library_paths.iter()
-        .find_map(|library_path| {
-            let steamapps = library_path.join("steamapps");
-            let manifest_path = steamapps.join(&manifest_filename);
-            let install_dir = match get_install_dir_from_manifest(&manifest_path) {
-                Ok(dir) => dir,
-                e => return Some(e)
-            };
-            let install_path = steamapps.join(install_dir);
-            Some(Ok(install_path))
+/// `base` joined with `relative`, each component spelled as it is on disk. A manifest's
+/// installdir can be capitalized differently from the folder, which a case-insensitive
+/// filesystem will happily open, but then backups and messages name a path that isn't
+/// quite the real one. An exact match wins, so of two folders differing only in case (on a
+/// case-sensitive filesystem) the one named is the one used; a component with no single
+/// match, or that isn't there at all, is left as it is.
+fn with_disk_case(base: &Path, relative: &Path) -> PathBuf {
+    let mut path = base.to_path_buf();
+    for component in relative.components() {
+        let name = component.as_os_str();
+        let on_disk = fs::read_dir(&path).ok().and_then(|entries| {
+            let names: Vec<_> = entries.filter_map(|entry| Some(entry.ok()?.file_name())).collect();
+            if names.iter().any(|entry| entry == name) {
+                return None;
+            }
+            let wanted = name.to_string_lossy().to_lowercase();
+            let mut matches = names.into_iter().filter(|entry| entry.to_string_lossy().to_lowercase() == wanted);
+            match (matches.next(), matches.next()) {
+                (Some(only), None) => Some(only),
+                _ => None
+            }
+        });
+        path.push(on_disk.as_deref().unwrap_or(name));
+    }
+    path
+}
+
+impl InstallLayout {
+    fn new(library_root: PathBuf, install_dir: PathBuf) -> InstallLayout {
+        let steamapps = library_root.join("steamapps");
+        let install_dir = with_disk_case(&steamapps, &install_dir);
+        let exe_path = with_disk_case(&install_dir, Path::new(EXE_SUBPATH));
+        InstallLayout { library_root, steamapps, install_dir, exe_path }
+    }
+
+    /// For an install given directly rather than found through Steam. The library is
+    /// guessed from the usual steamapps/common/<install dir> nesting.
+    fn at(install_dir: PathBuf) -> InstallLayout {
+        let steamapps = install_dir.parent().and_then(Path::parent).unwrap_or(&install_dir).to_path_buf();
+        let library_root = steamapps.parent().unwrap_or(&steamapps).to_path_buf();
+        let exe_path = install_dir.join(EXE_SUBPATH);
+        InstallLayout { library_root, steamapps, install_dir, exe_path }
+    }
+
+    /// The same install, with its executable somewhere other than the usual place.
+    fn with_exe(self, exe_subpath: &Path) -> InstallLayout {
+        let exe_path = with_disk_case(&self.install_dir, exe_subpath);
+        InstallLayout { exe_path, ..self }
+    }
+
+    /// For an executable given directly, whichever install it's in.
+    fn for_exe(exe_path: PathBuf) -> InstallLayout {
+        let levels = Path::new(EXE_SUBPATH).components().count();
+        let install_dir = Some(&exe_path)
+            .filter(|exe_path| exe_path.ends_with(EXE_SUBPATH))
+            .and_then(|exe_path| exe_path.ancestors().nth(levels))
+            .or_else(|| exe_path.parent())
+            .unwrap_or(&exe_path)
+            .to_path_buf();
+        InstallLayout { exe_path, ..InstallLayout::at(install_dir) }
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let entry = |path: &Path| json!({
+            "path":   path.to_string_lossy(),
+            "exists": path.exists(),
+        });
+
+        json!({
+            "library_root": entry(&self.library_root),
+            "steamapps":    entry(&self.steamapps),
+            "install_dir":  entry(&self.install_dir),
+            "exe_path":     entry(&self.exe_path),
         })
-        .unwrap_or(err_box(PatcherError::CantFindManifest { appid }))
+    }
+}
+
+// set for the whole run by --fixture-root; see `steam_root_candidates`
+static FIXTURE_ROOT: OnceLock<PathBuf> = OnceLock::new();
+
+/// Places Steam usually lives, most likely first. With --fixture-root, a fake Steam built
+/// for testing discovery (steamapps/libraryfolders.vdf, an appmanifest_<appid>.acf and the
+/// exe under steamapps/common) stands in for all of them, wherever $HOME is.
+fn steam_root_candidates() -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    if let Some(root) = FIXTURE_ROOT.get() {
+        return Ok(vec![root.clone()]);
+    }
+    let home: PathBuf = std::env::var("HOME")?.into();
+    Ok(vec![
+        home.join(".steam/steam"),                                  // native
+        home.join(".local/share/Steam"),                            // native, unlinked
+        home.join(".var/app/com.valvesoftware.Steam/.steam/steam"), // flatpak
+        home.join("snap/steam/common/.local/share/Steam"),          // snap
+    ])
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let install_path = find_install_path(49520)?;
+/// The Steam roots that actually exist here, canonicalized and without duplicates (the
+/// native candidates are often symlinks to one another).
+fn existing_steam_roots() -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut roots: Vec<PathBuf> = Vec::new();
+    for candidate in steam_root_candidates()? {
+        if let Ok(root) = candidate.canonicalize() {
+            if !roots.contains(&root) {
+                roots.push(root);
+            }
+        }
+    }
+    Ok(roots)
+}
 
-    // println!("49520 install path: {}", install_path.to_string_lossy());
+/// The game's name, as the manifest at `manifest_path` gives it.
+fn get_name_from_manifest(manifest_path: &Path) -> Option<String> {
+    let manifest = fs::read_to_string(manifest_path).ok()?;
+    let re = Regex::new(r#"(?m)^\s*"name"\s+"([^"]+)"\s*$"#).unwrap();
+    Some(re.captures(&manifest)?.get(1)?.as_str().to_string())
+}
 
-    let exe_path = install_path.join("Binaries/Win32/Borderlands2.exe");
-    // println!("exe_path: {}", exe_path.to_string_lossy());
+/// A game with a manifest in one of a Steam installation's libraries.
+#[derive(Clone)]
+struct InstalledGame {
+    appid:       u32,
+    name:        Option<String>,
+    manifest:    PathBuf,
+    library:     PathBuf,
+    // relative to the library's steamapps, as the manifest has it; None if the manifest
+    // couldn't be made sense of
+    install_dir: Option<PathBuf>,
+}
 
-    let mut file = OpenOptions::new()
-        .read(true)
-        .write(true)
-        .create(false)
-        .open(&exe_path)?;
+impl InstalledGame {
+    /// Only worked out for a game that's wanted, since matching the case on disk takes a
+    /// directory listing per path component, which adds up over every game in a slow library.
+    fn layout(&self) -> Option<InstallLayout> {
+        Some(InstallLayout::new(self.library.clone(), self.install_dir.clone()?))
+    }
+}
 
-    // check it's a file we know how to patch!
-    let state = get_exe_state(&mut file)?;
+// every Steam root scanned so far this run; see `scan_steam_root`
+static MANIFEST_SCANS: Mutex<Vec<(PathBuf, Arc<Vec<InstalledGame>>)>> = Mutex::new(Vec::new());
+
+/// Every library of `steam_root`, in Steam's order, with the root itself last.
+fn steam_libraries(steam_root: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut paths = load_libraries_vdf(&steam_root.join("steamapps/libraryfolders.vdf"))?;
+    paths.push(steam_root.to_path_buf());
+    Ok(paths)
+}
+
+/// Every game with a manifest in any library under `steam_root`, in the order Steam lists the
+/// libraries. Each root is only scanned once per run, however many games are looked up in it.
+fn scan_steam_root(steam_root: &Path) -> Result<Arc<Vec<InstalledGame>>, Box<dyn Error>> {
+    if let Some((_, games)) = MANIFEST_SCANS.lock().unwrap().iter().find(|(root, _)| root == steam_root) {
+        return Ok(games.clone());
+    }
+
+    let library_paths = steam_libraries(steam_root)?;
+    //  eprintln!("Steam library paths:");
+    //  for path in &library_paths { eprintln!("    {}", path.to_string_lossy()); }
 
-    let result: Result<(), Box<dyn Error>> = {
-        let action = if !state.patched {
-            // make a backup!
-            // TODO
+    let manifest_name = Regex::new(r"^appmanifest_(\d+)\.acf$").unwrap();
+    let mut games: Vec<InstalledGame> = Vec::new();
+    for library_path in library_paths {
+        // a library that's gone (an unplugged drive, say) just has no games
+        let entries = match fs::read_dir(library_path.join("steamapps")) {
+            Ok(entries) => entries,
+            Err(_) => continue
+        };
 
-            eprint!("Patching {} ...", exe_path.to_string_lossy());
-            Action::Apply
+        let mut manifests: Vec<(u32, PathBuf)> = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            let appid = path.file_name()
+                .and_then(|name| manifest_name.captures(&name.to_string_lossy())?.get(1)?.as_str().parse().ok());
+            if let Some(appid) = appid {
+                manifests.push((appid, path));
+            }
         }
-        else {
-            // unpatch
-            eprint!("Unpatching {} ...", exe_path.to_string_lossy());
-            Action::Undo
+        manifests.sort();
+
+        for (appid, manifest) in manifests {
+            let install_dir = get_install_dir_from_manifest(&manifest).ok();
+            // the root is often listed as a library too
+            let seen = games.iter().any(|game| {
+                game.appid == appid && game.library == library_path && game.install_dir == install_dir
+            });
+            if !seen {
+                let name = get_name_from_manifest(&manifest);
+                let library = library_path.clone();
+                games.push(InstalledGame { appid, name, manifest, library, install_dir });
+            }
+        }
+    }
+
+    let games = Arc::new(games);
+    MANIFEST_SCANS.lock().unwrap().push((steam_root.to_path_buf(), games.clone()));
+    Ok(games)
+}
+
+/// Every library under `steam_root` that has the game, in the order Steam lists them.
+fn installs_in_steam_root(appid: u32, steam_root: &Path)
+    -> Result<Vec<InstallLayout>, Box<dyn Error>>
+{
+    let mut installs = Vec::new();
+    for game in scan_steam_root(steam_root)?.iter().filter(|game| game.appid == appid) {
+        match game.layout() {
+            Some(layout) => installs.push(layout),
+            // libraries that don't have the game at all are skipped, but a manifest that's
+            // there and unreadable is an error; reading it again says what's wrong with it
+            None => {
+                let install_dir = get_install_dir_from_manifest(&game.manifest)?;
+                installs.push(InstallLayout::new(game.library.clone(), install_dir));
+            }
+        }
+    }
+    Ok(installs)
+}
+
+/// Lists every game installed under any Steam installation here.
+fn detect_games() -> Result<(), Box<dyn Error>> {
+    for root in existing_steam_roots()? {
+        let games = match scan_steam_root(&root) {
+            Ok(games) => games,
+            Err(e) => {
+                note!("Warning: skipping Steam at {}: {}", root.to_string_lossy(), e);
+                continue;
+            }
         };
 
-        // actually patch
-        state.version.modify_file(action, &mut file)?;
+        println!("{}:", root.to_string_lossy());
+        for game in games.iter() {
+            let supported = if game.appid == APPID { "  (supported)" } else { "" };
+            let install_dir = match &game.install_dir {
+                Some(install_dir) => {
+                    game.library.join("steamapps").join(install_dir).to_string_lossy().to_string()
+                }
+                None => format!("unreadable manifest {}", game.manifest.to_string_lossy())
+            };
+            println!("    {:>8}  {:<32}  {}{}",
+                game.appid, game.name.as_deref().unwrap_or("?"), install_dir, supported);
+        }
+    }
+    Ok(())
+}
+
+fn find_in_steam_root(appid: u32, steam_root: &Path)
+    -> Result<Option<InstallLayout>, Box<dyn Error>>
+{
+    Ok(installs_in_steam_root(appid, steam_root)?.into_iter().next())
+}
 
-        // verify
-        get_exe_state(&mut file)?;
+/// Every install of the game under every Steam installation here, with the root each was
+/// found under. A root that can't be read is warned about and skipped, so one broken
+/// installation doesn't hide the others.
+fn find_all_installs(appid: u32) -> Result<Vec<(PathBuf, InstallLayout)>, Box<dyn Error>> {
+    let mut installs = Vec::new();
+    for root in existing_steam_roots()? {
+        match installs_in_steam_root(appid, &root) {
+            Ok(found) => installs.extend(found.into_iter().map(|layout| (root.clone(), layout))),
+            Err(e) => note!("Warning: skipping Steam at {}: {}", root.to_string_lossy(), e)
+        }
+    }
+    Ok(installs)
+}
 
-        Ok(())
-    };
+/// Whether there's a build we know at `exe_path`.
+fn is_known_build(exe_path: &Path, scope: HashScope) -> bool {
+    File::open(exe_path).ok().is_some_and(|mut file| get_exe_state(&mut file, scope).is_ok())
+}
 
-    if let Err(e) = result {
-        eprintln!("Error while modifying executable: {}\nYou should restore from your backup.", e);
-        // TODO: restore backup
+/// The architecture --arch narrows the candidate executables down to, if any, and the
+/// candidates it's ruled out so far.
+struct ArchFilter {
+    arch:     Option<Arch>,
+    excluded: Vec<(PathBuf, Arch)>,
+}
+
+impl ArchFilter {
+    fn new(arch: Option<Arch>) -> ArchFilter {
+        ArchFilter { arch, excluded: Vec::new() }
     }
-    else {
-        eprintln!("OK!");
+
+    /// Whether `exe_path` may be the game, going only by its header, so that a candidate
+    /// built for something else is never hashed. One whose header can't be read is left for
+    /// whatever reads it next to complain about.
+    fn admits(&mut self, exe_path: &Path) -> bool {
+        let arch = match self.arch {
+            Some(arch) => arch,
+            None => return true
+        };
+        let found = match File::open(exe_path).and_then(|mut file| binary::read_arch(&mut file)) {
+            Ok(found) => found,
+            Err(_) => return true
+        };
+        if found != arch && !self.excluded.iter().any(|(path, _)| path == exe_path) {
+            self.excluded.push((exe_path.to_path_buf(), found));
+        }
+        found == arch
     }
 
-    Ok(())
+    /// `admits`, and the executable is a build we know.
+    fn admits_known(&mut self, exe_path: &Path, scope: HashScope) -> bool {
+        self.admits(exe_path) && is_known_build(exe_path, scope)
+    }
+
+    /// The error for having ruled out everything, if anything was ruled out at all.
+    fn rejection(self) -> Option<Box<dyn Error>> {
+        match self.arch {
+            Some(arch) if !self.excluded.is_empty() => {
+                Some(Box::new(PatcherError::NoExeForArch { arch, found: self.excluded }))
+            }
+            _ => None
+        }
+    }
+}
+
+/// The last resort of --scan-common, for an install whose manifest is gone: the first folder
+/// in any library's steamapps/common whose executable `filter` admits and is a build we know.
+fn scan_common(
+    steam_root: Option<&Path>, exe_subpath: Option<&Path>, scope: HashScope, filter: &mut ArchFilter
+) -> Result<Option<InstallLayout>, Box<dyn Error>>
+{
+    let roots = match steam_root {
+        Some(root) => vec![root.canonicalize()?],
+        None => existing_steam_roots()?
+    };
+
+    for root in roots {
+        // without a readable library list, there's still the root's own library
+        let libraries = steam_libraries(&root).unwrap_or_else(|_| vec![root.clone()]);
+        for library in libraries {
+            let entries = match fs::read_dir(library.join("steamapps/common")) {
+                Ok(entries) => entries,
+                Err(_) => continue
+            };
+            let mut dirs: Vec<PathBuf> = entries
+                .filter_map(|entry| Some(entry.ok()?.path()))
+                .filter(|path| path.is_dir())
+                .collect();
+            dirs.sort();
+
+            for dir in dirs {
+                let layout = InstallLayout::at(dir);
+                let layout = match exe_subpath {
+                    Some(subpath) => layout.with_exe(subpath),
+                    None => layout
+                };
+                if filter.admits_known(&layout.exe_path, scope) {
+                    note!("No manifest for the game, but found a known build at {}",
+                        layout.exe_path.to_string_lossy());
+                    return Ok(Some(layout));
+                }
+            }
+        }
+    }
+    Ok(None)
+}
+
+fn find_install_path(appid: u32, steam_root: Option<&Path>)
+    -> Result<InstallLayout, Box<dyn Error>>
+{
+    let roots = existing_steam_roots()?;
+    let steam_root = match steam_root {
+        Some(root) => root.canonicalize()?,
+        None => match roots.first() {
+            Some(root) => root.clone(),
+            None => return err_box(PatcherError::NoSteamRoot)
+        }
+    };
+
+    match find_in_steam_root(appid, &steam_root)? {
+        Some(layout) => Ok(layout),
+        None => {
+            // flatpak and native Steam can both be installed, each with its own libraries
+            let other_roots = roots.into_iter().filter(|root| *root != steam_root).collect();
+            err_box(PatcherError::CantFindManifest { appid, other_roots })
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Add the version definitions from a signed .blpatch bundle
+    Import {
+        file: PathBuf,
+    },
+    /// Write a copy of this program with version definitions built in
+    Embed {
+        /// Where to write the new executable
+        #[arg(long, short)]
+        output: PathBuf,
+        /// Definition files, as in versions.d
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Make sure there's a good backup, patch the game if it isn't already, and start it
+    Play,
+    /// Show what's been done to the game's executable over time
+    History,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum TargetState {
+    Patched, Unpatched
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum HashFormat {
+    /// Lowercase hex, as in `VERSIONS` and version definitions
+    Hex,
+    /// The raw digest, written to stdout as is
+    Bytes,
+    Base64,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ScriptKind {
+    /// POSIX shell, with dd
+    Sh,
+    /// PowerShell
+    Ps1,
+}
+
+/// The exit status for --target-state when there was nothing to do.
+const EXIT_ALREADY_IN_STATE: i32 = 10;
+
+/// --quiet-status's exit statuses; unlike everywhere else, 1 isn't a failure.
+const QUIET_PATCHED:   i32 = 0;
+const QUIET_UNPATCHED: i32 = 1;
+const QUIET_UNKNOWN:   i32 = 2;
+const QUIET_FAILED:    i32 = 3;
+
+#[derive(Parser)]
+#[command(about = "Patches Borderlands 2 to unlock the developer console")]
+#[command(after_help = "Exit status: 0 on success; 10 with --target-state if the executable was \
+    already in that state, so nothing changed; 1 on any failure. With --quiet-status: 0 if \
+    patched, 1 if unpatched, 2 if an unknown build, 3 on any failure, a bad command line \
+    included.")]
+struct Options {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Print the resolved install layout as JSON and exit
+    #[arg(long)]
+    print_layout: bool,
+
+    /// Read the executable from stdin and write the toggled result to stdout, without
+    /// touching any Steam install. The whole file is held in memory while patching.
+    #[arg(long)]
+    stdio: bool,
+
+    /// Look for the game under this Steam installation instead of the first one found
+    #[arg(long, value_name = "DIR")]
+    steam_root: Option<PathBuf>,
+
+    /// Patch the game installed in this directory, without looking through Steam
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["file", "steam_root", "all_roots"])]
+    install_path: Option<PathBuf>,
+
+    /// Where the executable is within the install, if renamed or localized; see config.toml
+    /// to remember this
+    #[arg(long, value_name = "SUBPATH", conflicts_with = "file")]
+    exe: Option<PathBuf>,
+
+    /// Patch this executable, without looking through Steam
+    #[arg(long, value_name = "EXE", conflicts_with_all = ["steam_root", "all_roots"])]
+    file: Option<PathBuf>,
+
+    /// If Steam has no record of the game, look through every library's steamapps/common for
+    /// a known build instead. Slow.
+    #[arg(long, conflicts_with_all = ["file", "install_path"])]
+    scan_common: bool,
+
+    /// If Steam has no record of the game, or what it installed isn't a build we know, look
+    /// for a known build among what Steam's non-Steam shortcuts launch (shortcuts.vdf)
+    #[arg(long, conflicts_with_all = ["file", "install_path"])]
+    shortcuts: bool,
+
+    /// Only consider executables built for this architecture (x86 or x86_64) while finding
+    /// the game, going by their headers: candidates for another are skipped without hashing
+    #[arg(long, value_name = "ARCH", value_parser = parse_arch, conflicts_with_all = ["file", "install_path"])]
+    arch: Option<Arch>,
+
+    /// Give up looking through Steam for the game after this many seconds
+    #[arg(long, value_name = "SECONDS")]
+    timeout_discovery: Option<u64>,
+
+    /// List every game installed under any Steam installation, and exit
+    #[arg(long)]
+    detect_games: bool,
+
+    /// Search every Steam installation (native, flatpak, snap) rather than just the first,
+    /// and insist on there being only one install of the game. With --print-layout, print
+    /// every install found.
+    #[arg(long, conflicts_with = "steam_root")]
+    all_roots: bool,
+
+    /// Keep backups in this directory rather than beside the executable
+    #[arg(long, value_name = "DIR")]
+    backup_dir: Option<PathBuf>,
+
+    /// Back up only the bytes this tool changes, instead of the whole executable. This is
+    /// enough to undo patching, but not to recover from any other damage.
+    #[arg(long)]
+    minimal_backup: bool,
+
+    /// Leave at least this many MiB free when writing a whole copy of the executable (a full
+    /// backup, a restore or --output), refusing before starting if there wouldn't be
+    #[arg(long, value_name = "MIB", default_value_t = 64)]
+    min_free_space: u64,
+
+    /// Don't read each change back as soon as it's written; the whole file is still verified
+    /// after patching
+    #[arg(long)]
+    fast: bool,
+
+    /// Print, as JSON, each change's expected original and patched bytes alongside what's on
+    /// disk now. Only reads the executable.
+    #[arg(long)]
+    diff_json: bool,
+
+    /// Print nothing, and exit with the executable's state instead (see below). Only reads
+    /// the executable.
+    #[arg(long, conflicts_with_all = [
+        "print_layout", "stdio", "detect_games", "hash", "compare_versions", "check_change",
+        "diff_json", "export_ips", "emit_script", "list_backups", "check_backup",
+        "explain_unknown", "output", "restore", "revert_to_build", "target_state",
+    ])]
+    quiet_status: bool,
+
+    /// Print the executable's SHA-1, known build or not, and exit. Only reads the executable.
+    #[arg(long)]
+    hash: bool,
+
+    /// How --hash prints it [default: hex]
+    #[arg(long, value_name = "FORMAT", requires = "hash")]
+    hash_format: Option<HashFormat>,
+
+    /// Write the installed build's changes as an IPS patch against its unpatched executable
+    #[arg(long, value_name = "FILE")]
+    export_ips: Option<PathBuf>,
+
+    /// Print a standalone script that checks and writes exactly the bytes patching (or
+    /// unpatching, going by --target-state) would, instead of doing it. Only reads the
+    /// executable.
+    #[arg(long, value_name = "SHELL")]
+    emit_script: Option<ScriptKind>,
+
+    /// List the backups beside the executable
+    #[arg(long)]
+    list_backups: bool,
+
+    /// With --list-backups, print JSON instead
+    #[arg(long, requires = "list_backups")]
+    json: bool,
+
+    /// With --list-backups, rehash each backup to check it (slow)
+    #[arg(long, requires = "list_backups")]
+    verify: bool,
+
+    /// Say whether there's a sound backup to restore the build installed now from, checking
+    /// each backup against it; fails if there isn't
+    #[arg(long)]
+    check_backup: bool,
+
+    /// If the executable is read-only, make it writable while changing it, then read-only
+    /// again
+    #[arg(long)]
+    clear_readonly: bool,
+
+    /// Never write anything: no patching, restoring or backups, only inspecting. Setting
+    /// BL_PATCHER_READONLY=1 does the same, for machines where the tool must never change
+    /// the game.
+    #[arg(long)]
+    read_only: bool,
+
+    /// Say more about what's going on, such as where a symlinked executable really is
+    #[arg(long, short)]
+    verbose: bool,
+
+    /// Patch even a file under a system directory such as /usr or C:\Windows, which is
+    /// otherwise refused as a likely mistake
+    #[arg(long)]
+    force: bool,
+
+    /// Write the result to this path instead, leaving the executable as it is
+    #[arg(long, value_name = "PATH")]
+    output: Option<PathBuf>,
+
+    /// Make sure the executable ends up in this state rather than toggling it, exiting with
+    /// 10 if it already was
+    #[arg(long, value_name = "STATE")]
+    target_state: Option<TargetState>,
+
+    /// Advanced, for machines whose build never changes: don't hash the executable to tell
+    /// the build, but take it to be an unpatched known build and patch it, checking only the
+    /// bytes at each change first and after. A full backup is still made first, named for
+    /// what the executable really hashes to.
+    #[arg(long, conflicts_with_all = ["assume_patched", "target_state", "output", "restore", "revert_to_build", "auto_offset"])]
+    assume_unpatched: bool,
+
+    /// Advanced: as --assume-unpatched, but take the executable to be patched, and unpatch it
+    #[arg(long, conflicts_with_all = ["target_state", "output", "restore", "revert_to_build", "auto_offset"])]
+    assume_patched: bool,
+
+    /// Do exactly what running with no arguments always has: find the game, toggle it, and
+    /// finish with just "OK!". A backup is still made, but not mentioned, and nothing is
+    /// recorded in the history. For scripts that depend on that: running with no arguments
+    /// toggles through the 0.x releases, and from 1.0 only reports the state; this flag keeps
+    /// the toggle through 1.x, and goes in 2.0.
+    #[arg(long, conflicts_with_all = [
+        "target_state", "output", "restore", "revert_to_build", "assume_unpatched", "assume_patched",
+        "auto_offset", "quiet_status", "print_layout", "stdio", "detect_games", "diff_json", "hash",
+        "export_ips", "emit_script", "list_backups", "check_backup", "compare_versions",
+        "check_change", "explain_unknown",
+    ])]
+    legacy: bool,
+
+    /// Refuse to do anything unless the executable's SHA1, as it is now, is exactly this: for
+    /// pinning the one build a fleet of machines should have
+    #[arg(long, value_name = "SHA1", value_parser = parse_sha1, conflicts_with = "stdio")]
+    expect_hash: Option<String>,
+
+    /// Restore the executable from its backup
+    #[arg(long)]
+    restore: bool,
+
+    /// Restore the full backup of the build with this SHA1, e.g. to undo a game update
+    #[arg(long, value_name = "HASH")]
+    revert_to_build: Option<String>,
+
+    /// Identify the build by its code sections alone, so that a re-signed executable still
+    /// matches a known version
+    #[arg(long)]
+    code_hash: bool,
+
+    /// Show how far each named change moved between two known builds, given by name, index
+    /// or hash
+    #[arg(long, num_args = 2, value_names = ["FROM", "TO"])]
+    compare_versions: Option<Vec<String>>,
+
+    /// Allow the maintainers' tools: --check-change NAME [--fixture EXE...] checks a named
+    /// change's bytes in every known build that has it, against copies of those builds;
+    /// --fixture-root DIR looks for the game only in a fake Steam installation at DIR
+    #[arg(long)]
+    dev: bool,
+
+    #[arg(long, value_name = "NAME", requires = "dev", hide = true)]
+    check_change: Option<String>,
+
+    #[arg(long, value_name = "EXE", requires = "check_change", num_args = 1.., hide = true)]
+    fixture: Vec<PathBuf>,
+
+    #[arg(long, value_name = "DIR", requires = "dev", hide = true, conflicts_with_all = ["file", "install_path"])]
+    fixture_root: Option<PathBuf>,
+
+    /// Recompute the PE header checksum after changing the executable. This changes the
+    /// file's hash, so builds have to be identified by --code-hash
+    #[arg(long, requires = "code_hash")]
+    fix_checksum: bool,
+
+    /// If the build is unknown, offer to have Steam verify the game files, which fixes the
+    /// usual cause: a corrupt or partial download
+    #[arg(long)]
+    offer_verify: bool,
+
+    /// If the build is unknown but looks like a known one with its code shifted, offer to
+    /// patch it at the shifted offsets (after making a backup)
+    #[arg(long)]
+    auto_offset: bool,
+
+    /// For an unknown build, say how much of the nearest known build's patch still lines up
+    /// with it, as a guide to whether --auto-offset has a chance. Only reads the executable.
+    #[arg(long)]
+    explain_unknown: bool,
+
+    /// Stay running and answer JSON status/apply/undo requests on this Unix socket. Not yet
+    /// available on Windows, which would need a named pipe instead
+    #[cfg(feature = "serve")]
+    #[arg(long, value_name = "SOCKET", conflicts_with_all = ["legacy", "quiet_status"])]
+    serve: Option<PathBuf>,
+
+    /// Refuse to patch if a change doesn't land on the instruction its definition expects,
+    /// instead of just warning
+    #[cfg(feature = "disasm")]
+    #[arg(long)]
+    strict: bool,
+
+    /// Write the changes through a memory map of the executable instead of seeking to each,
+    /// falling back to that if the file can't be mapped. Only the writes go through the map;
+    /// hashing still reads the file. As with --fast, changes aren't read back as they're
+    /// written, though the whole file is still verified after patching
+    #[cfg(feature = "mmap")]
+    #[arg(long)]
+    mmap: bool,
+}
+
+fn parse_arch(name: &str) -> Result<Arch, String> {
+    name.parse().map_err(|()| format!("unknown architecture {}; expected x86 or x86_64", name))
+}
+
+fn parse_sha1(hash: &str) -> Result<String, String> {
+    if hash.len() != 40 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("{} isn't a SHA1 (40 hex digits)", hash));
+    }
+    Ok(hash.to_ascii_lowercase())
+}
+
+impl Options {
+    fn hash_scope(&self) -> HashScope {
+        if self.code_hash { HashScope::Code } else { HashScope::File }
+    }
+
+    /// Whether the exe is taken to be patched without hashing it, if it is; see `assume_state`.
+    fn assumed_patched(&self) -> Option<bool> {
+        match (self.assume_unpatched, self.assume_patched) {
+            (true, _) => Some(false),
+            (_, true) => Some(true),
+            _ => None
+        }
+    }
+}
+
+/// Prints what each change of the installed build expects against what's actually there.
+fn diff_json(exe_path: &Path, scope: HashScope) -> Result<(), Box<dyn Error>> {
+    let mut file = File::open(exe_path)?;
+    let state = get_exe_state(&mut file, scope)?;
+
+    let mut changes = Vec::new();
+    for change in state.version.changes {
+        let mut current = vec![0u8; change.original.len()];
+        file.seek(SeekFrom::Start(change.offset))?;
+        file.read_exact(&mut current)?;
+
+        let status = if current == change.original { "original" }
+            else if current == change.patch { "patched" }
+            else { "other" };
+        changes.push(json!({
+            "name":     change.name,
+            "offset":   change.offset,
+            "original": to_hex(change.original),
+            "patch":    to_hex(change.patch),
+            "current":  to_hex(&current),
+            "status":   status,
+        }));
+    }
+
+    let diff = json!({
+        "path":    exe_path.to_string_lossy(),
+        "version": state.version.label(),
+        "hash":    hash_file(&mut file)?.to_string(),
+        "patched": state.patched,
+        "changes": changes,
+    });
+    println!("{}", serde_json::to_string_pretty(&diff)?);
+    Ok(())
+}
+
+/// --check-backup: goes through the backups of `exe_path`, sorting out which could restore
+/// the build installed now, and fails unless one of them is sound.
+fn check_backup(exe_path: &Path, scope: HashScope) -> Result<(), Box<dyn Error>> {
+    let current = match get_exe_state(&mut File::open(exe_path)?, scope) {
+        Ok(state) => Some(state.version),
+        Err(e) if is_unknown_version(&*e) => None,
+        Err(e) => return Err(e)
+    };
+    let label = |hash: &str| defs::known_versions().iter()
+        .find(|version| version.unpatched_hash == hash)
+        .map_or(hash.to_string(), |version| version.label().to_string());
+
+    let mut good = None;
+    let mut stale = Vec::new();
+    for backup in Backup::list(exe_path)? {
+        let path = backup.path.to_string_lossy().to_string();
+        if current.is_none_or(|version| version.unpatched_hash != backup.hash) {
+            println!("stale    {}  (build {}, not the one installed now)", path, label(&backup.hash));
+            stale.push(backup);
+            continue;
+        }
+
+        if backup.verify()?.1 {
+            println!("good     {}", path);
+            good = Some(backup);
+        }
+        else {
+            println!("CORRUPT  {}", path);
+        }
+    }
+
+    let version = match (current, good) {
+        (Some(version), Some(_)) => {
+            eprintln!("{} can be restored to stock build {} from its backup.",
+                exe_path.to_string_lossy(), version.label());
+            return Ok(());
+        }
+        (Some(version), None) => version.label().to_string(),
+        (None, _) => {
+            // the backups were all taken of something else; a full one can still roll back to it
+            if stale.iter().any(|backup| backup.kind == BackupKind::Full) {
+                eprintln!("The installed build is unknown (a game update?); --revert-to-build can \
+                    put back one of the stale builds above.");
+            }
+            "the unknown build installed now".to_string()
+        }
+    };
+    Err(format!("No sound backup of {} for {}", version, exe_path.to_string_lossy()).into())
+}
+
+/// Lists the backups of `exe_path`, for people or (with `json`) for other programs.
+fn list_backups(exe_path: &Path, json: bool, verify: bool) -> Result<(), Box<dyn Error>> {
+    let mut entries = Vec::new();
+    for backup in Backup::list(exe_path)? {
+        let metadata = fs::metadata(&backup.path)?;
+        let mtime = metadata.modified()?
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|since| since.as_secs())
+            .unwrap_or(0);
+        let (actual, valid) = if verify {
+            let (actual, valid) = backup.verify()?;
+            (actual, Some(valid))
+        }
+        else {
+            (None, None)
+        };
+        let known = defs::known_versions().iter()
+            .find(|version| version.hashes().any(|hash| hash == backup.hash));
+
+        entries.push(json!({
+            "path":          backup.path.to_string_lossy(),
+            "kind":          match backup.kind { BackupKind::Full => "full", BackupKind::Minimal => "minimal" },
+            "hash":          backup.hash,
+            "actual_hash":   actual,
+            "size":          metadata.len(),
+            "mtime":         mtime,
+            "known_version": known.map(|version| version.label()),
+            "valid":         valid,
+        }));
+    }
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&entries)?);
+        return Ok(());
+    }
+
+    if entries.is_empty() {
+        eprintln!("No backups of {}", exe_path.to_string_lossy());
+    }
+    for entry in &entries {
+        let status = match entry["valid"].as_bool() {
+            Some(true)  => "ok",
+            Some(false) => "CORRUPT",
+            None        => "-",
+        };
+        println!("{}  {:<7}  {:>10}  {:<7}  {:<20}  {}",
+            entry["hash"].as_str().unwrap_or(""),
+            entry["kind"].as_str().unwrap_or(""),
+            entry["size"],
+            status,
+            entry["known_version"].as_str().unwrap_or("unknown build"),
+            entry["path"].as_str().unwrap_or(""));
+    }
+    Ok(())
+}
+
+fn restore_exe(exe_path: &Path, scope: HashScope) -> Result<(), Box<dyn Error>> {
+    let known_hash = File::open(exe_path).ok()
+        .and_then(|mut file| get_exe_state(&mut file, scope).ok())
+        .map(|state| state.version.unpatched_hash.to_string());
+
+    // a known build says exactly which backup it wants; otherwise (say, after a failed patch)
+    // there has to be only one candidate
+    let hash = match known_hash {
+        Some(hash) => hash,
+        None => {
+            let mut hashes: Vec<String> = Backup::list(exe_path)?
+                .into_iter()
+                .map(|backup| backup.hash)
+                .collect();
+            hashes.dedup();
+            match hashes.len() {
+                1 => hashes.remove(0),
+                0 => return err_box(PatcherError::NoBackup {
+                    exe: exe_path.to_string_lossy().to_string()
+                }),
+                _ => return err_box(PatcherError::AmbiguousBackup { hashes })
+            }
+        }
+    };
+
+    let backup = match Backup::find(exe_path, &hash)? {
+        Some(backup) => backup,
+        None => return err_box(PatcherError::NoBackup {
+            exe: exe_path.to_string_lossy().to_string()
+        })
+    };
+
+    eprint!("Restoring {} from {} ...", exe_path.to_string_lossy(), backup.path.to_string_lossy());
+    backup.restore(exe_path)?;
+    eprintln!("OK!");
+    history::record(APPID, exe_path, "restored", None, &backup.hash, &[]);
+    Ok(())
+}
+
+/// Puts back the full backup of a particular build, which needn't be the one installed now;
+/// this is how to roll back a game update, given a backup from before it.
+fn revert_to_build(exe_path: &Path, hash: &str) -> Result<(), Box<dyn Error>> {
+    let hash = hash.to_ascii_lowercase();
+
+    // a minimal backup only holds the bytes we change, so it can't turn one build into another
+    let mut stored: Vec<String> = Vec::new();
+    let mut found = None;
+    for backup in Backup::list(exe_path)? {
+        if backup.kind != BackupKind::Full {
+            continue;
+        }
+        if backup.hash == hash {
+            found = Some(backup);
+        }
+        else {
+            stored.push(backup.hash);
+        }
+    }
+
+    let backup = match found {
+        Some(backup) => backup,
+        None => return err_box(PatcherError::NoBuildBackup { hash, stored })
+    };
+
+    // the build being replaced may have no backup of its own yet, and would be lost
+    let current = hash_file(&mut File::open(exe_path)?)?.to_string();
+    let current_backup = match Backup::find_sound(exe_path, &current)? {
+        Some(backup) => backup,
+        None => Backup::create_full(exe_path, &current)?
+    };
+    eprintln!("Backup: {}", current_backup.path.to_string_lossy());
+
+    eprint!("Reverting {} to build {} from {} ...",
+        exe_path.to_string_lossy(), hash, backup.path.to_string_lossy());
+    backup.restore(exe_path)?;
+    eprintln!("OK!");
+    history::record(APPID, exe_path, "restored", None, &hash, &[]);
+    Ok(())
+}
+
+fn patch_stdio(scope: HashScope) -> Result<(), Box<dyn Error>> {
+    // hashing and patching both need to seek, so buffer the whole thing; this costs as much
+    // memory as the executable is large (about 30 MB for BL2)
+    let mut buf = Vec::new();
+    io::stdin().read_to_end(&mut buf)?;
+    let mut file = Cursor::new(buf);
+
+    let state = get_exe_state(&mut file, scope)?;
+    let action = if !state.patched {
+        eprint!("Patching stdin ...");
+        Action::Apply
+    }
+    else {
+        eprint!("Unpatching stdin ...");
+        Action::Undo
+    };
+
+    state.version.modify_file(action, &mut file, true)?;
+    verify_state(&mut file, &state, action, scope)?;
+
+    io::stdout().write_all(file.get_ref())?;
+    eprintln!("OK!");
+    Ok(())
+}
+
+/// Prints how far each named change moved between two builds. Changes are matched up by
+/// name; unnamed ones can't be.
+fn compare_versions(from: &Version, to: &Version) -> Result<(), Box<dyn Error>> {
+    println!("{} -> {}", from.label(), to.label());
+
+    let find = |version: &Version, name| version.changes.iter().find(|change| change.name == Some(name));
+    for change in from.changes {
+        let name = match change.name {
+            Some(name) => name,
+            None => continue
+        };
+        match find(to, name) {
+            Some(moved) => println!("    {:<20} {:#010x} -> {:#010x}  {}",
+                name, change.offset, moved.offset, signed_hex(moved.offset as i64 - change.offset as i64)),
+            None => println!("    {:<20} {:#010x} -> (none)", name, change.offset)
+        }
+    }
+    for change in to.changes {
+        if let Some(name) = change.name.filter(|&name| find(from, name).is_none()) {
+            println!("    {:<20} (none)     -> {:#010x}", name, change.offset);
+        }
+    }
+
+    let unnamed = from.changes.iter().chain(to.changes).filter(|change| change.name.is_none()).count();
+    if unnamed != 0 {
+        println!("{} unnamed changes not compared", unnamed);
+    }
+    Ok(())
+}
+
+/// --check-change, for maintainers: lists the change called `name` in every known build that
+/// has one, and checks that its bytes really are at its offset in each of `fixtures` (copies
+/// of those builds, patched or not).
+fn check_change(name: &str, fixtures: &[PathBuf], scope: HashScope) -> Result<(), Box<dyn Error>> {
+    let mut copies = Vec::new();
+    for path in fixtures {
+        let mut file = File::open(path)?;
+        let state = get_exe_state(&mut file, scope)
+            .map_err(|e| format!("{}: {}", path.to_string_lossy(), e))?;
+        copies.push((path, file, state));
+    }
+
+    // the first build found to have the change
+    let mut first: Option<(&Version, &Change)> = None;
+    let mut checked = vec![false; copies.len()];
+    let mut mismatches = 0;
+    for &version in defs::known_versions() {
+        let change = match version.changes.iter().find(|change| change.name == Some(name)) {
+            Some(change) => change,
+            None => continue
+        };
+
+        println!("{:<24} {:#010x}  {} -> {}",
+            version.label(), change.offset, to_hex(change.original), to_hex(change.patch));
+        match first {
+            Some((first, other)) if other.original != change.original || other.patch != change.patch => {
+                println!("    (not the same bytes as in {})", first.label());
+            }
+            Some(_) => {}
+            None => first = Some((version, change))
+        }
+
+        for (index, (path, file, state)) in copies.iter_mut().enumerate() {
+            if !std::ptr::eq(state.version, version) {
+                continue;
+            }
+            checked[index] = true;
+
+            let expected = if state.patched { change.patch } else { change.original };
+            let mut found = vec![0u8; expected.len()];
+            file.seek(SeekFrom::Start(change.offset))?;
+            file.read_exact(&mut found)?;
+
+            let state = if state.patched { "patched" } else { "unpatched" };
+            if found == expected {
+                println!("    ok in {} ({})", path.to_string_lossy(), state);
+            }
+            else {
+                println!("    MISMATCH in {} ({}): found {}", path.to_string_lossy(), state, to_hex(&found));
+                mismatches += 1;
+            }
+        }
+    }
+
+    if first.is_none() {
+        return Err(format!("No known build has a change called {}", name).into());
+    }
+    for ((path, _, state), checked) in copies.iter().zip(checked) {
+        if !checked {
+            eprintln!("Warning: {} is build {}, which has no change called {}",
+                path.to_string_lossy(), state.version.label(), name);
+        }
+    }
+    if mismatches != 0 {
+        return Err(format!("The change called {} isn't where it should be in {} of the fixtures", name, mismatches).into());
+    }
+    Ok(())
+}
+
+/// Brings the PE header checksum up to date with the rest of the file.
+fn fix_checksum(file: &mut File) -> Result<(), Box<dyn Error>> {
+    let field = match binary::pe_checksum_offset(file)? {
+        Some(field) => field,
+        None => {
+            eprint!(" (no PE checksum to fix)");
+            return Ok(());
+        }
+    };
+
+    let checksum = binary::pe_checksum(file, field)?;
+    file.seek(SeekFrom::Start(field))?;
+    file.write_all(&checksum.to_le_bytes())?;
+    eprint!(" checksum {:#010x} ...", checksum);
+    Ok(())
+}
+
+/// Refuses --fix-checksum before anything is written if the result couldn't be verified:
+/// that needs a code hash for the state `action` leaves `version` in.
+fn check_fix_checksum(version: &Version, action: Action) -> Result<(), Box<dyn Error>> {
+    let code_hash = match action {
+        Action::Apply => version.patched_code_hash,
+        Action::Undo  => version.unpatched_code_hash,
+    };
+    if code_hash.is_none() {
+        return err_box(PatcherError::NoCodeHash { build: version.label(), action });
+    }
+    Ok(())
+}
+
+/// Asks a yes/no question on the terminal. Anything but a yes, including end of input, is a
+/// no.
+fn confirm(question: &str) -> Result<bool, io::Error> {
+    eprint!("{} [y/N] ", question);
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+/// Opens the exe for patching and works out which build it is; with --assume-*, which build
+/// it is taken to be.
+fn open_exe(exe_path: &Path, scope: HashScope, assumed_patched: Option<bool>)
+    -> Result<(File, ExeState), Box<dyn Error>>
+{
+    refuse_writes()?;
+    refuse_hard_links(exe_path)?;
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(false)
+        .open(exe_path)?;
+
+    // check it's a file we know how to patch!
+    let state = match assumed_patched {
+        Some(patched) => assume_state(&mut file, patched)?,
+        None => get_exe_state(&mut file, scope)?
+    };
+    Ok((file, state))
+}
+
+/// For --assume-*: the one known build whose changes' bytes are all there in `file`, patched
+/// or not as `patched` says, without hashing it. Where nothing else differs, that's as good
+/// as knowing the build; where something else does, nothing here will notice.
+fn assume_state<F: Read + Seek>(file: &mut F, patched: bool) -> Result<ExeState, Box<dyn Error>> {
+    let arch = binary::read_arch(file)?;
+    let mut fits = Vec::new();
+    for &version in defs::known_versions() {
+        let expected = |change: &Change| if patched { change.patch } else { change.original };
+        if version.arch == arch && changes_read(file, version.changes, expected)? {
+            fits.push(version);
+        }
+    }
+
+    match fits[..] {
+        [version] => Ok(ExeState { version, patched, assumed: true }),
+        _ => err_box(PatcherError::AssumedState { patched, builds: fits.len() })
+    }
+}
+
+/// Whether each of `changes` is `expected(change)` in `file`. The bytes are only read where
+/// the file is long enough to hold them.
+fn changes_read<F, E>(file: &mut F, changes: &[Change], expected: E) -> Result<bool, io::Error>
+    where F: Read + Seek, E: Fn(&Change) -> &'static [u8]
+{
+    let len = file.seek(SeekFrom::End(0))?;
+    for change in changes {
+        let expected = expected(change);
+        if change.offset + expected.len() as u64 > len {
+            return Ok(false);
+        }
+        let mut found = vec![0u8; expected.len()];
+        file.seek(SeekFrom::Start(change.offset))?;
+        file.read_exact(&mut found)?;
+        if found != expected {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// For --assume-*, in place of `verify_state`: checks that each change reads back as
+/// `action` wrote it.
+fn verify_changes<F: Read + Seek>(file: &mut F, version: &Version, action: Action)
+    -> Result<(), Box<dyn Error>>
+{
+    for change in version.changes {
+        let written = match action {
+            Action::Apply => change.patch,
+            Action::Undo  => change.original
+        };
+        if !changes_read(file, slice::from_ref(change), |_| written)? {
+            return err_box(PatcherError::Unwritten { offset: change.offset });
+        }
+    }
+    Ok(())
+}
+
+/// Says what the changes just applied do, as one line, e.g. "Dev commands enabled; 'set'
+/// command enabled." Changes without a description are left out.
+fn print_summary(changes: &[Change]) {
+    let summary = changes.iter()
+        .filter_map(|change| change.description)
+        .collect::<Vec<_>>()
+        .join("; ");
+
+    let mut chars = summary.chars();
+    if let Some(first) = chars.next() {
+        eprintln!("{}{}.", first.to_uppercase(), chars.as_str());
+    }
+}
+
+/// `Version::modify_file` on the exe (or a copy of it), reading each change back unless
+/// --fast; or with --mmap, through a memory map, where there would be nothing to read back.
+fn modify_in_place(version: &Version, action: Action, file: &mut File, options: &Options)
+    -> Result<(), io::Error>
+{
+    #[cfg(feature = "mmap")]
+    if options.mmap {
+        match mmap::map(file) {
+            Ok(mut map) => return mmap::modify(version, action, &mut map),
+            Err(e) => eprint!(" (can't map it: {}; writing it instead)", e)
+        }
+    }
+    version.modify_file(action, file, !options.fast)
+}
+
+/// Applies or undoes the patch, making a backup first when applying, or whenever the state was
+/// only assumed. If anything goes wrong partway, the exe is restored from its backup before
+/// the error is returned.
+fn modify_exe(exe_path: &Path, mut file: File, state: &ExeState, action: Action, options: &Options)
+    -> Result<(), Box<dyn Error>>
+{
+    let unpatched_hash = state.version.unpatched_hash;
+    if options.fix_checksum {
+        check_fix_checksum(state.version, action)?;
+    }
+
+    #[cfg(feature = "disasm")]
+    if let Action::Apply = action {
+        disasm::check(&mut file, state.version, options.strict)?;
+    }
+
+    // make a backup!
+    let backup = if state.assumed {
+        // there's no knowing the build without hashing it, and a backup named for the wrong one
+        // would only be found out once it had been restored over the exe; so it's hashed just
+        // for this, and backed up whole, patched or not
+        let hash = hash_file(&mut file)?.to_string();
+        Some(match Backup::find_sound(exe_path, &hash)? {
+            Some(backup) => backup,
+            None => Backup::create_full(exe_path, &hash)?
+        })
+    }
+    else if let Action::Apply = action {
+        let kind = if options.minimal_backup { BackupKind::Minimal } else { BackupKind::Full };
+        Some(match Backup::find_sound(exe_path, unpatched_hash)? {
+            Some(backup) => backup,
+            None => Backup::create(exe_path, &mut file, state.version, kind)?
+        })
+    }
+    else {
+        None
+    };
+    if let Some(backup) = &backup {
+        if !options.legacy {
+            eprintln!("Backup: {}", backup.path.to_string_lossy());
+        }
+    }
+
+    // a closure, so that `?` lands here rather than skipping the restore below
+    let result: Result<(), Box<dyn Error>> = (|| {
+        match action {
+            Action::Apply => eprint!("Patching {} ...", exe_path.to_string_lossy()),
+            Action::Undo  => eprint!("Unpatching {} ...", exe_path.to_string_lossy()),
+        }
+
+        // actually patch; a failure partway leaves a mix of the two builds, which the backup
+        // below puts right
+        modify_in_place(state.version, action, &mut file, options).map_err(|e| write_error(e, exe_path))?;
+        if options.fix_checksum {
+            fix_checksum(&mut file)?;
+        }
+
+        // verify, undoing as strictly as applying
+        if state.assumed {
+            verify_changes(&mut file, state.version, action)?;
+        } else {
+            verify_state(&mut file, state, action, options.hash_scope())?;
+        }
+
+        Ok(())
+    })();
+
+    if let Err(e) = result {
+        eprintln!();
+        drop(file);
+        if matches!(e.downcast_ref(), Some(PatcherError::VerifyUnknown { .. })) {
+            eprintln!("Patching left the executable as no known build; putting it back.");
+        }
+
+        // the one just taken, or for an undo, the one taken when it was patched
+        let backup = match backup {
+            Some(backup) => Ok(Some(backup)),
+            None => Backup::find_sound(exe_path, unpatched_hash)
+        };
+        match backup {
+            Ok(Some(backup)) => {
+                eprint!("Restoring from {} ...", backup.path.to_string_lossy());
+                match backup.roll_back(exe_path) {
+                    Ok(()) => eprintln!("OK!"),
+                    Err(e) => eprintln!("{}\nYou should restore from your backup.", e)
+                }
+            }
+            _ => eprintln!("No backup to restore from; you should verify the game files in Steam.")
+        }
+
+        return Err(e);
+    }
+
+    eprintln!("OK!");
+    if options.legacy {
+        return Ok(());
+    }
+    let event = match action {
+        Action::Apply => "patched",
+        Action::Undo  => "unpatched",
+    };
+    history::record(APPID, exe_path, event, None, unpatched_hash, state.version.changes);
+    if let Action::Apply = action {
+        print_summary(state.version.changes);
+    }
+    Ok(())
+}
+
+/// Finds the game, trying wherever it was last time before searching Steam again.
+fn locate_game(appid: u32, options: &Options) -> Result<InstallLayout, Box<dyn Error>> {
+    if let Some(exe_path) = &options.file {
+        return Ok(InstallLayout::for_exe(exe_path.clone()));
+    }
+
+    // a renamed executable is still in the same install
+    let exe_subpath = match &options.exe {
+        Some(subpath) => Some(subpath.clone()),
+        None => config::Config::load()?.exe_subpath(appid)
+    };
+
+    if let Some(install_dir) = &options.install_path {
+        let layout = InstallLayout::at(install_dir.clone());
+        return Ok(match &exe_subpath {
+            Some(subpath) => layout.with_exe(subpath),
+            None => layout
+        });
+    }
+
+    let search = Search {
+        appid,
+        steam_root:  options.steam_root.clone(),
+        // an explicit Steam root, or a request to search them all, means looking again, not
+        // wherever we looked last; and a fake one mustn't be confused with the real one in the
+        // cache
+        use_cache:   options.steam_root.is_none() && !options.all_roots
+            && options.fixture_root.is_none(),
+        all_roots:   options.all_roots,
+        scan_common: options.scan_common,
+        shortcuts:   options.shortcuts,
+        arch:        options.arch,
+        scope:       options.hash_scope(),
+        exe_subpath,
+    };
+    let (layout, found) = match options.timeout_discovery {
+        Some(seconds) => search.run_with_timeout(seconds)?,
+        None => search.run()?
+    };
+
+    // not even our own cache is written in read-only mode, nor does a fake Steam belong in it
+    let found = match found {
+        Some(found) if !READ_ONLY.load(Ordering::Relaxed) && options.fixture_root.is_none() => found,
+        _ => return Ok(layout)
+    };
+    // --legacy says nothing the original didn't
+    if let Err(e) = cache::store(appid, &found.install, found.shortcut) {
+        if !options.legacy {
+            note!("Warning: couldn't remember the install path: {}", e);
+        }
+    }
+    Ok(layout)
+}
+
+/// What `locate_game` looks for the game with, owned so that the search can run on a thread of
+/// its own under --timeout-discovery.
+struct Search {
+    appid:       u32,
+    steam_root:  Option<PathBuf>,
+    use_cache:   bool,
+    all_roots:   bool,
+    scan_common: bool,
+    shortcuts:   bool,
+    arch:        Option<Arch>,
+    scope:       HashScope,
+    exe_subpath: Option<PathBuf>,
+}
+
+/// Where `Search` found the game, as the cache should remember it: the install before any exe
+/// subpath, unless it came from a shortcut, whose exe is wherever it is, --exe or no.
+struct Found {
+    install:  InstallLayout,
+    shortcut: bool,
+}
+
+impl Search {
+    fn with_exe(&self, layout: InstallLayout) -> InstallLayout {
+        match &self.exe_subpath {
+            Some(subpath) => layout.with_exe(subpath),
+            None => layout
+        }
+    }
+
+    /// Looks in the cache, then through Steam, then wherever the fallbacks asked for point.
+    /// Alongside the layout is where it was found, for the cache; or nothing if it came from the
+    /// cache.
+    fn run(&self) -> Result<(InstallLayout, Option<Found>), Box<dyn Error>> {
+        let mut filter = ArchFilter::new(self.arch);
+        if self.use_cache {
+            if let Some(layout) = cache::lookup(self.appid, self.scope, self.exe_subpath.as_deref()) {
+                if filter.admits(&layout.exe_path) {
+                    return Ok((layout, None));
+                }
+            }
+        }
+
+        let steam_root = self.steam_root.as_deref();
+        let (layout, found) = match discover(self.appid, steam_root, self.all_roots) {
+            Ok(found) => {
+                let layout = self.with_exe(found.clone());
+                // the game may be launched from somewhere else by a shortcut of its own
+                let shortcut = if self.shortcuts && !filter.admits_known(&layout.exe_path, self.scope) {
+                    shortcuts::find_known(steam_root, self.scope, &mut filter)?
+                } else {
+                    None
+                };
+                let (layout, found) = match shortcut {
+                    Some(found) => (found.clone(), Found { install: found, shortcut: true }),
+                    None => (layout, Found { install: found, shortcut: false })
+                };
+                if !filter.admits(&layout.exe_path) {
+                    return Err(filter.rejection().unwrap());
+                }
+                (layout, found)
+            }
+            Err(e) => {
+                let mut fallback = None;
+                if self.scan_common {
+                    fallback = scan_common(
+                        steam_root, self.exe_subpath.as_deref(), self.scope, &mut filter
+                    )?.map(|layout| {
+                        let install = InstallLayout::at(layout.install_dir.clone());
+                        (layout, Found { install, shortcut: false })
+                    });
+                }
+                if fallback.is_none() && self.shortcuts {
+                    fallback = shortcuts::find_known(steam_root, self.scope, &mut filter)?
+                        .map(|found| (found.clone(), Found { install: found, shortcut: true }));
+                }
+                match fallback {
+                    Some(fallback) => fallback,
+                    // what's worth knowing is that there were candidates, just not for --arch
+                    None => return Err(filter.rejection().unwrap_or(e))
+                }
+            }
+        };
+        Ok((layout, Some(found)))
+    }
+
+    /// `run`, giving up after `seconds`. The search carries on in the background until we exit,
+    /// since there's no interrupting a stuck read of a network library.
+    fn run_with_timeout(self, seconds: u64)
+        -> Result<(InstallLayout, Option<Found>), Box<dyn Error>>
+    {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            // errors in general aren't Send, but ours and io's are, and only those are told
+            // apart later; anything else comes back as its message
+            let result = self.run().map_err(|e| -> Box<dyn Error + Send + Sync> {
+                match e.downcast::<PatcherError>() {
+                    Ok(e) => e,
+                    Err(e) => match e.downcast::<io::Error>() {
+                        Ok(e) => e,
+                        Err(e) => e.to_string().into()
+                    }
+                }
+            });
+            let _ = sender.send(result);
+        });
+
+        match receiver.recv_timeout(std::time::Duration::from_secs(seconds)) {
+            Ok(result) => result.map_err(|e| e as Box<dyn Error>),
+            Err(_) => err_box(PatcherError::DiscoveryTimeout { seconds })
+        }
+    }
+}
+
+/// Searches Steam for the game.
+fn discover(appid: u32, steam_root: Option<&Path>, all_roots: bool)
+    -> Result<InstallLayout, Box<dyn Error>>
+{
+    if !all_roots {
+        return find_install_path(appid, steam_root);
+    }
+
+    let mut installs = find_all_installs(appid)?;
+    match installs.len() {
+        0 => err_box(PatcherError::CantFindManifest { appid, other_roots: Vec::new() }),
+        1 => Ok(installs.remove(0).1),
+        _ => err_box(PatcherError::AmbiguousInstall {
+            installs: installs.into_iter().map(|(root, install)| (root, install.exe_path)).collect()
+        })
+    }
+}
+
+/// The real file behind `exe_path`. Through a symlink or junction (a per-game shim, say), the
+/// path we found may be on another volume than the exe itself, and backups and the rename
+/// that restores one need to be beside the exe itself.
+fn resolve_exe(exe_path: &Path, verbose: bool) -> Result<PathBuf, Box<dyn Error>> {
+    let real = match exe_path.canonicalize() {
+        Ok(real) => real,
+        // one that isn't there at all fails more usefully when it's opened
+        Err(_) => return Ok(exe_path.to_path_buf())
+    };
+
+    if !fs::metadata(&real)?.is_file() {
+        return err_box(PatcherError::NotAFile {
+            path:   exe_path.to_string_lossy().to_string(),
+            target: real.to_string_lossy().to_string(),
+        });
+    }
+    if verbose && real != exe_path {
+        note!("{} is really {}", exe_path.to_string_lossy(), real.to_string_lossy());
+    }
+    Ok(real)
+}
+
+/// Places no game is installed, where a mistyped --file, --exe or --output could only do harm.
+const SYSTEM_DIRS: &[&str] = if cfg!(windows) {
+    &[r"C:\Windows"]
+}
+else {
+    &["/usr", "/bin", "/sbin", "/lib", "/lib64", "/etc", "/boot", "/System"]
+};
+
+/// Fails, unless `force`, if `path` (already canonical) is under one of `SYSTEM_DIRS`.
+fn refuse_system_path(path: &Path, force: bool) -> Result<(), Box<dyn Error>> {
+    if force {
+        return Ok(());
+    }
+
+    // Windows paths compare case-insensitively, and come back from canonicalize() as \\?\C:\...
+    let text = path.to_string_lossy();
+    let text = text.strip_prefix(r"\\?\").unwrap_or(&text);
+    let normalize = |text: &str| if cfg!(windows) { text.to_lowercase() } else { text.to_string() };
+    let found = SYSTEM_DIRS.iter()
+        .find(|dir| Path::new(&normalize(text)).starts_with(normalize(dir)));
+
+    match found {
+        Some(dir) => err_box(PatcherError::SystemPath { path: path.to_string_lossy().to_string(), dir }),
+        None => Ok(())
+    }
+}
+
+/// Fails if the exe has other names, which would see any change made to it in place: a
+/// link meant to share one copy between installs, or a backup made by linking.
+fn refuse_hard_links(exe_path: &Path) -> Result<(), Box<dyn Error>> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let links = match fs::metadata(exe_path) {
+            Ok(metadata) => metadata.nlink(),
+            Err(_) => return Ok(())
+        };
+        if links > 1 {
+            return err_box(PatcherError::HardLinked { path: exe_path.to_string_lossy().to_string(), links });
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = exe_path;
+    Ok(())
+}
+
+fn run(options: Options) -> Result<(), Box<dyn Error>> {
+    let read_only_env = std::env::var("BL_PATCHER_READONLY").is_ok_and(|value| value == "1");
+    if options.read_only || read_only_env {
+        READ_ONLY.store(true, Ordering::Relaxed);
+    }
+    MIN_FREE_SPACE.store(options.min_free_space.saturating_mul(MIB), Ordering::Relaxed);
+    QUIET.store(options.quiet_status, Ordering::Relaxed);
+
+    match &options.command {
+        Some(Command::Import { file }) => {
+            refuse_writes()?;
+            return bundle::import(file);
+        }
+        Some(Command::Embed { output, files }) => {
+            refuse_writes()?;
+            return embedded::embed(output, files);
+        }
+        Some(Command::History) => return history::print(APPID),
+        Some(Command::Play) | None => {}
+    }
+
+    defs::load_known_versions()?;
+
+    if let Some(dir) = &options.backup_dir {
+        backup::set_dir(dir.clone());
+    }
+
+    if let Some(root) = &options.fixture_root {
+        let _ = FIXTURE_ROOT.set(root.clone());
+    }
+
+    if let Some(selectors) = &options.compare_versions {
+        return compare_versions(defs::select(&selectors[0])?, defs::select(&selectors[1])?);
+    }
+
+    if let Some(name) = &options.check_change {
+        return check_change(name, &options.fixture, options.hash_scope());
+    }
+
+    if options.stdio {
+        return patch_stdio(options.hash_scope());
+    }
+
+    if options.detect_games {
+        return detect_games();
+    }
+
+    if options.all_roots && options.print_layout {
+        let installs: Vec<_> = find_all_installs(APPID)?.iter()
+            .map(|(root, layout)| {
+                let mut entry = layout.to_json();
+                entry["steam_root"] = json!(root.to_string_lossy());
+                entry
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&installs)?);
+        return Ok(());
+    }
+
+    let layout = locate_game(APPID, &options)?;
+
+    if options.print_layout {
+        println!("{}", serde_json::to_string_pretty(&layout.to_json())?);
+        return Ok(());
+    }
+
+    let exe_path = resolve_exe(&layout.exe_path, options.verbose)?;
+
+    if let Some(expected) = &options.expect_hash {
+        let found = hash_file(&mut File::open(&exe_path)?)?.to_string();
+        if found != *expected {
+            return err_box(PatcherError::UnexpectedHash { expected: expected.clone(), found });
+        }
+    }
+
+    if let Some(Command::Play) = options.command {
+        refuse_system_path(&exe_path, options.force)?;
+        return play(&exe_path, &options);
+    }
+
+    if options.hash {
+        let digest = hash_file(&mut File::open(&exe_path)?)?.bytes();
+        match options.hash_format.unwrap_or(HashFormat::Hex) {
+            HashFormat::Hex    => println!("{}", to_hex(&digest)),
+            HashFormat::Bytes  => io::stdout().write_all(&digest)?,
+            HashFormat::Base64 => println!("{}", to_base64(&digest)),
+        }
+        return Ok(());
+    }
+
+    if options.quiet_status {
+        let status = match get_exe_state(&mut File::open(&exe_path)?, options.hash_scope()) {
+            Ok(state) if state.patched => QUIET_PATCHED,
+            Ok(_) => QUIET_UNPATCHED,
+            Err(e) if is_unknown_version(&*e) => QUIET_UNKNOWN,
+            Err(e) => return Err(e)
+        };
+        std::process::exit(status);
+    }
+
+    #[cfg(feature = "serve")]
+    if let Some(socket) = &options.serve {
+        refuse_system_path(&exe_path, options.force)?;
+        #[cfg(unix)]
+        return serve::serve(socket, &exe_path, &options);
+        #[cfg(not(unix))]
+        return Err(format!("--serve {} needs Unix sockets; there's no named-pipe server for Windows yet",
+            socket.to_string_lossy()).into());
+    }
+
+    if options.diff_json {
+        return diff_json(&exe_path, options.hash_scope());
+    }
+
+    if options.explain_unknown {
+        return relocate::explain_unknown(&exe_path, options.hash_scope());
+    }
+
+    if let Some(path) = &options.export_ips {
+        let mut file = File::open(&exe_path)?;
+        let state = get_exe_state(&mut file, options.hash_scope())?;
+        return ips::export(state.version, &mut file, path);
+    }
+
+    if let Some(kind) = options.emit_script {
+        let state = get_exe_state(&mut File::open(&exe_path)?, options.hash_scope())?;
+        let action = match pick_action(&exe_path, &state, options.target_state) {
+            Some(action) => action,
+            None => std::process::exit(EXIT_ALREADY_IN_STATE)
+        };
+        match kind {
+            ScriptKind::Sh  => print!("{}", script::sh(state.version, action)),
+            ScriptKind::Ps1 => print!("{}", script::ps1(state.version, action)),
+        }
+        return Ok(());
+    }
+
+    if options.list_backups {
+        return list_backups(&exe_path, options.json, options.verify);
+    }
+
+    if options.check_backup {
+        return check_backup(&exe_path, options.hash_scope());
+    }
+
+    // the rest all write, to the exe or a copy of it
+    refuse_system_path(&exe_path, options.force)?;
+
+    if let Some(output) = &options.output {
+        return patch_copy(&exe_path, output, &options);
+    }
+
+    // everything from here on writes to the exe
+    let changed = with_writable(&exe_path, options.clear_readonly, || {
+        if options.restore {
+            restore_exe(&exe_path, options.hash_scope())?;
+            return Ok(true);
+        }
+
+        if let Some(hash) = &options.revert_to_build {
+            revert_to_build(&exe_path, hash)?;
+            return Ok(true);
+        }
+
+        let (file, state) = match open_exe(&exe_path, options.hash_scope(), options.assumed_patched()) {
+            Err(e) if options.auto_offset && is_unknown_version(&*e) => {
+                relocate::patch_relocated(&exe_path)?;
+                return Ok(true);
+            }
+            result => result?
+        };
+        match pick_action(&exe_path, &state, options.target_state) {
+            Some(action) => modify_exe(&exe_path, file, &state, action, &options).map(|()| true),
+            None => Ok(false)
+        }
+    })?;
+
+    if !changed {
+        std::process::exit(EXIT_ALREADY_IN_STATE);
+    }
+    Ok(())
+}
+
+/// `permissions` with writing allowed: for the owner on Unix, where read-only means no one
+/// may write.
+fn writable(permissions: &fs::Permissions) -> fs::Permissions {
+    let mut writable = permissions.clone();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        writable.set_mode(permissions.mode() | 0o200);
+    }
+    #[cfg(not(unix))]
+    #[allow(clippy::permissions_set_readonly_false)]
+    writable.set_readonly(false);
+    writable
+}
+
+// set for the whole run by --read-only or BL_PATCHER_READONLY; see `refuse_writes`
+static READ_ONLY: AtomicBool = AtomicBool::new(false);
+
+// set for the whole run by --quiet-status; see `note!`
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Fails in read-only mode. Everything that writes the exe, a copy of it or a backup asks
+/// first, however it was reached.
+fn refuse_writes() -> Result<(), Box<dyn Error>> {
+    if READ_ONLY.load(Ordering::Relaxed) {
+        return err_box(PatcherError::ReadOnlyMode);
+    }
+    Ok(())
+}
+
+/// Runs `write`, which changes the exe, clearing its read-only attribute for the duration
+/// if it has one and we're allowed to.
+fn with_writable<T, F>(exe_path: &Path, clear_readonly: bool, write: F) -> Result<T, Box<dyn Error>>
+    where F: FnOnce() -> Result<T, Box<dyn Error>>
+{
+    refuse_writes()?;
+    refuse_hard_links(exe_path)?;
+
+    let permissions = match fs::metadata(exe_path) {
+        Ok(metadata) if metadata.permissions().readonly() => metadata.permissions(),
+        // a missing exe fails more usefully in `write`
+        _ => return write()
+    };
+    if !clear_readonly {
+        return err_box(PatcherError::ReadOnly { path: exe_path.to_string_lossy().to_string() });
+    }
+
+    fs::set_permissions(exe_path, writable(&permissions))?;
+    eprintln!("Cleared the read-only attribute of {}", exe_path.to_string_lossy());
+
+    let result = write();
+
+    // a restore may have replaced the file, but it's the path that was read-only
+    match fs::set_permissions(exe_path, permissions) {
+        Ok(()) => eprintln!("Made {} read-only again", exe_path.to_string_lossy()),
+        Err(e) => eprintln!("Warning: couldn't make {} read-only again: {}", exe_path.to_string_lossy(), e)
+    }
+    result
+}
+
+/// Toggles, or heads for `target` if there is one. `None` if it's already there, so
+/// there's nothing to do.
+fn pick_action(exe_path: &Path, state: &ExeState, target: Option<TargetState>) -> Option<Action> {
+    match target {
+        Some(target) if (target == TargetState::Patched) == state.patched => {
+            let target = if state.patched { "patched" } else { "unpatched" };
+            eprintln!("{} is already {}", exe_path.to_string_lossy(), target);
+            None
+        }
+        // otherwise it's in the other state, either way
+        _ => Some(if !state.patched { Action::Apply } else { Action::Undo })
+    }
+}
+
+/// Writes a patched (or unpatched) copy of the exe to `output`, leaving the exe alone. No
+/// backup is needed, since the original is never touched.
+fn patch_copy(exe_path: &Path, output: &Path, options: &Options) -> Result<(), Box<dyn Error>> {
+    if output.canonicalize().ok().as_deref() == Some(exe_path) {
+        return Err(format!("--output {} is the executable itself", output.to_string_lossy()).into());
+    }
+    // the output needn't exist yet, but where it's going does
+    let output_dir = output.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    if let Ok(output_dir) = output_dir.canonicalize() {
+        refuse_system_path(&output_dir.join(output.file_name().unwrap_or_default()), options.force)?;
+    }
+
+    refuse_writes()?;
+
+    let state = get_exe_state(&mut File::open(exe_path)?, options.hash_scope())?;
+    let action = match pick_action(exe_path, &state, options.target_state) {
+        Some(action) => action,
+        None => std::process::exit(EXIT_ALREADY_IN_STATE)
+    };
+    if options.fix_checksum {
+        check_fix_checksum(state.version, action)?;
+    }
+
+    // build the copy under a temporary name, so a failure never leaves a bad one at `output`
+    let name = output.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+    let temp = output.with_file_name(format!("{}.partial", name));
+    let result: Result<(), Box<dyn Error>> = (|| {
+        check_free_space(output, fs::metadata(exe_path)?.len())?;
+        fs::copy(exe_path, &temp).map_err(|e| write_error(e, &temp))?;
+        // the copy is ours to write, whatever the original's attributes
+        fs::set_permissions(&temp, writable(&fs::metadata(&temp)?.permissions()))?;
+        let mut file = OpenOptions::new().read(true).write(true).open(&temp)?;
+
+        match action {
+            Action::Apply => eprint!("Patching a copy at {} ...", output.to_string_lossy()),
+            Action::Undo  => eprint!("Unpatching a copy at {} ...", output.to_string_lossy()),
+        }
+        modify_in_place(state.version, action, &mut file, options).map_err(|e| write_error(e, &temp))?;
+        if options.fix_checksum {
+            fix_checksum(&mut file)?;
+        }
+        verify_state(&mut file, &state, action, options.hash_scope())?;
+        drop(file);
+
+        fs::rename(&temp, output)?;
+        Ok(())
+    })();
+
+    if let Err(e) = result {
+        eprintln!();
+        let _ = fs::remove_file(&temp);
+        return Err(e);
+    }
+
+    eprintln!("OK!");
+    if let Action::Apply = action {
+        print_summary(state.version.changes);
+    }
+    Ok(())
+}
+
+/// Points the user at Steam's file verification for `appid`, and starts it if they like.
+fn offer_verify(appid: u32) {
+    let url = format!("steam://validate/{}", appid);
+    eprintln!("An unknown build is most often a corrupt or partial download; Steam can check \
+        the game files with {}", url);
+
+    match confirm("Verify them now?") {
+        Ok(true) => {}
+        _ => return
+    }
+
+    if let Err(e) = open_url(&url) {
+        eprintln!("Couldn't open {}: {}", url, e);
+    }
+}
+
+/// Hands `url` to whatever the desktop opens URLs with, which for steam:// is Steam.
+fn open_url(url: &str) -> Result<(), io::Error> {
+    let launched = if cfg!(windows) {
+        std::process::Command::new("cmd").args(["/C", "start", "", url]).spawn()
+    }
+    else if cfg!(target_os = "macos") {
+        std::process::Command::new("open").arg(url).spawn()
+    }
+    else {
+        std::process::Command::new("xdg-open").arg(url).spawn()
+    };
+    launched.map(|_| ())
+}
+
+/// The `play` subcommand: checks there's a sound backup of the build (taking one if the exe
+/// is still unpatched), patches if need be, and only then starts the game through Steam.
+fn play(exe_path: &Path, options: &Options) -> Result<(), Box<dyn Error>> {
+    with_writable(exe_path, options.clear_readonly, || {
+        let (file, state) = open_exe(exe_path, options.hash_scope(), options.assumed_patched())?;
+
+        match Backup::find(exe_path, state.version.unpatched_hash)? {
+            Some(backup) => {
+                if !backup.verify()?.1 {
+                    return err_box(PatcherError::BadBackup { path: backup.path.to_string_lossy().to_string() });
+                }
+                eprintln!("Backup: {}", backup.path.to_string_lossy());
+            }
+            // patching takes one first
+            None if !state.patched => {}
+            // and there's nothing to take one of now
+            None => return err_box(PatcherError::NoBackup { exe: exe_path.to_string_lossy().to_string() })
+        }
+
+        match pick_action(exe_path, &state, Some(TargetState::Patched)) {
+            Some(action) => modify_exe(exe_path, file, &state, action, options),
+            None => Ok(())
+        }
+    })?;
+
+    let url = format!("steam://rungameid/{}", APPID);
+    eprintln!("Starting the game ({})", url);
+    open_url(&url).map_err(|e| format!("Couldn't open {}: {}", url, e).into())
+}
+
+fn main() {
+    let options = match Options::try_parse() {
+        Ok(options) => options,
+        // clap's own status for a bad command line, 2, would read as an unknown build
+        Err(e) if e.use_stderr() && std::env::args_os().any(|arg| arg == "--quiet-status") => {
+            std::process::exit(QUIET_FAILED);
+        }
+        Err(e) => e.exit()
+    };
+    // clap can't make an argument conflict with subcommands, only with each other
+    if options.quiet_status && options.command.is_some() {
+        std::process::exit(QUIET_FAILED);
+    }
+    if options.legacy && options.command.is_some() {
+        Options::command().error(ErrorKind::ArgumentConflict,
+            "--legacy only toggles, so it can't be used with a subcommand").exit();
+    }
+    let wants_verify = options.offer_verify;
+    let quiet_status = options.quiet_status;
+
+    if let Err(e) = run(options) {
+        if quiet_status {
+            std::process::exit(QUIET_FAILED);
+        }
+        eprintln!("Error: {}", e);
+        if wants_verify && is_unknown_version(&*e) {
+            offer_verify(APPID);
+        }
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        proptest::{collection::vec, prelude::*},
+    };
+
+    /// A build made of `changes` and nothing else, leaked to be `'static` like a real one.
+    fn version_with(changes: Vec<Change>) -> &'static Version {
+        Box::leak(Box::new(Version {
+            name:                Some("test"),
+            arch:                Arch::X86,
+            algo:                HashAlgo::Sha1,
+            size:                None,
+            unpatched_hash:      "0000000000000000000000000000000000000000",
+            patched_hash:        "1111111111111111111111111111111111111111",
+            unpatched_code_hash: None,
+            patched_code_hash:   None,
+            changes:             Box::leak(changes.into_boxed_slice()),
+        }))
+    }
+
+    fn leak(bytes: &[u8]) -> &'static [u8] {
+        Box::leak(bytes.to_vec().into_boxed_slice())
+    }
+
+    /// Changes taking `buffer`'s own bytes as their originals, laid out in order from `layout`'s
+    /// (gap before, patch) pairs; any that would run off the end are dropped.
+    fn changes_over(buffer: &[u8], layout: &[(usize, Vec<u8>)]) -> Vec<Change> {
+        let mut changes = Vec::new();
+        let mut offset = 0;
+        for (gap, patch) in layout {
+            offset += gap;
+            if offset + patch.len() > buffer.len() {
+                break;
+            }
+            changes.push(Change {
+                offset:   offset as u64,
+                original: leak(&buffer[offset .. offset + patch.len()]),
+                patch:    leak(patch),
+                ..Change::BARE
+            });
+            offset += patch.len();
+        }
+        changes
+    }
+
+    /// Reads `installdir` out of a manifest giving it as `value`, written as VDF would have it.
+    fn install_dir_for(value: &str) -> Result<PathBuf, Box<dyn Error>> {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = dir.path().join("appmanifest_49520.acf");
+        fs::write(&manifest, format!(
+            "\"AppState\"\n{{\n\t\"appid\"\t\t\"49520\"\n\t\"installdir\"\t\t\"{}\"\n}}\n", value
+        )).unwrap();
+        get_install_dir_from_manifest(&manifest)
+    }
+
+    #[test]
+    fn legacy_only_toggles() {
+        Options::command().debug_assert();
+        assert!(Options::try_parse_from(["bl-patcher", "--legacy"]).is_ok());
+        assert!(Options::try_parse_from(["bl-patcher", "--legacy", "--file", "Borderlands2.exe"]).is_ok());
+        for mode in [&["--hash"][..], &["--print-layout"], &["--quiet-status"], &["--restore"],
+            &["--target-state", "patched"], &["--emit-script", "sh"], &["--compare-versions", "0", "1"]]
+        {
+            let args = ["bl-patcher", "--legacy"].iter().chain(mode);
+            assert!(Options::try_parse_from(args).is_err(), "--legacy allowed with {:?}", mode);
+        }
+    }
+
+    #[test]
+    fn quiet_status_only_reports() {
+        assert!(Options::try_parse_from(["bl-patcher", "--quiet-status", "--code-hash"]).is_ok());
+        for mode in ["--hash", "--print-layout", "--stdio", "--diff-json", "--restore"] {
+            let args = ["bl-patcher", "--quiet-status", mode];
+            assert!(Options::try_parse_from(args).is_err(), "--quiet-status allowed with {}", mode);
+        }
+    }
+
+    #[test]
+    fn truncated_pe_is_unknown() {
+        // cut off before e_lfanew, and then in the COFF header
+        let mut cut_in_coff = vec![0u8; 0x48];
+        cut_in_coff[.. 2].copy_from_slice(b"MZ");
+        cut_in_coff[0x3c] = 0x40;
+        cut_in_coff[0x40 .. 0x44].copy_from_slice(b"PE\0\0");
+        for file in [b"MZ".to_vec(), cut_in_coff] {
+            match get_exe_state(&mut Cursor::new(file), HashScope::Code) {
+                Err(e) => assert!(is_unknown_version(&*e), "unexpected error: {}", e),
+                Ok(_) => panic!("a truncated file was taken for a known build")
+            }
+        }
+    }
+
+    #[test]
+    fn manifest_install_dir() {
+        assert_eq!(install_dir_for("Borderlands 2").unwrap(), Path::new("common/Borderlands 2"));
+        // VDF doubles backslashes
+        assert_eq!(install_dir_for(r"Games\\Borderlands 2").unwrap(), Path::new("common/Games/Borderlands 2"));
+        assert_eq!(install_dir_for("./Borderlands 2/").unwrap(), Path::new("common/Borderlands 2"));
+    }
+
+    #[test]
+    fn manifest_install_dir_rejects() {
+        for value in ["", ".", "/", "..", "../Borderlands 2", r"Borderlands 2\\..\\..", "a/../../b"] {
+            assert!(install_dir_for(value).is_err(), "accepted installdir {:?}", value);
+        }
+    }
+
+    #[test]
+    fn disk_case() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path();
+        fs::create_dir_all(base.join("Borderlands 2/Binaries/Win32")).unwrap();
+
+        assert_eq!(with_disk_case(base, Path::new("borderlands 2")), base.join("Borderlands 2"));
+        assert_eq!(
+            with_disk_case(base, Path::new("borderlands 2/binaries/WIN32/Borderlands2.exe")),
+            base.join("Borderlands 2/Binaries/Win32/Borderlands2.exe")
+        );
+        assert_eq!(with_disk_case(base, Path::new("Tiny Tina")), base.join("Tiny Tina"));
+
+        // only a case-sensitive filesystem can hold both
+        if fs::create_dir(base.join("borderlands 2")).is_ok() {
+            assert_eq!(with_disk_case(base, Path::new("borderlands 2")), base.join("borderlands 2"));
+            assert_eq!(with_disk_case(base, Path::new("Borderlands 2")), base.join("Borderlands 2"));
+            // and with no exact match, neither is a better guess than the other
+            assert_eq!(with_disk_case(base, Path::new("BORDERLANDS 2")), base.join("BORDERLANDS 2"));
+        }
+    }
+
+    #[test]
+    fn library_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let libraries: Vec<PathBuf> = (1 ..= 10).map(|index| {
+            let library = dir.path().join(format!("library{}", index));
+            fs::create_dir(&library).unwrap();
+            library.canonicalize().unwrap()
+        }).collect();
+
+        // written out of order, as a table's keys may come in any order
+        let mut vdf = String::from("\"LibraryFolders\"\n{\n\t\"TimeNextStatsReport\"\t\t\"0\"\n");
+        for index in [10, 2, 9, 1, 5, 3, 8, 4, 7, 6] {
+            vdf += &format!("\t\"{}\"\t\t\"{}\"\n", index, libraries[index - 1].to_string_lossy());
+        }
+        vdf += "}\n";
+        let path = dir.path().join("libraryfolders.vdf");
+        fs::write(&path, vdf).unwrap();
+
+        assert_eq!(load_libraries_vdf(&path).unwrap(), libraries);
+    }
+
+    /// A file on a full disk: reads and seeks work, but writes fail with `error`, or, without
+    /// one, write nothing, as a short write does.
+    struct FullDisk {
+        file:  Cursor<Vec<u8>>,
+        error: Option<io::ErrorKind>,
+    }
+
+    impl Read for FullDisk {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.file.read(buf)
+        }
+    }
+
+    impl Write for FullDisk {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            match self.error {
+                Some(kind) => Err(kind.into()),
+                None => Ok(0)
+            }
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Seek for FullDisk {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.file.seek(pos)
+        }
+    }
+
+    #[test]
+    fn full_disk() {
+        let version = version_with(changes_over(&[0; 16], &[(4, vec![0x90; 4])]));
+        let path = Path::new("Borderlands2.exe");
+
+        for error in [Some(io::ErrorKind::StorageFull), None] {
+            let mut file = FullDisk { file: Cursor::new(vec![0; 16]), error };
+            let e = version.modify_file(Action::Apply, &mut file, true).unwrap_err();
+            match write_error(e, path).downcast_ref() {
+                Some(PatcherError::InsufficientSpace { path, space: None }) => {
+                    assert_eq!(path, "Borderlands2.exe");
+                }
+                _ => panic!("a failed write ({:?}) wasn't taken for want of space", error)
+            }
+        }
+
+        // but not just any failed write
+        let error = Some(io::ErrorKind::PermissionDenied);
+        let mut file = FullDisk { file: Cursor::new(vec![0; 16]), error };
+        let e = version.modify_file(Action::Apply, &mut file, true).unwrap_err();
+        assert!(write_error(e, path).downcast_ref::<PatcherError>().is_none());
+    }
+
+    /// Builds the fake Steam --fixture-root would point at, and sets it as the one for the run.
+    /// Its own library is empty, and the game is in a second library, under a folder
+    /// capitalized differently from the manifest's installdir. Returns (the temporary directory
+    /// it's all in, to be held until done with, Steam root, second library).
+    fn fake_steam() -> (tempfile::TempDir, PathBuf, PathBuf) {
+        let guard = tempfile::tempdir().unwrap();
+        let dir = guard.path().canonicalize().unwrap();
+        let root = dir.join("Steam");
+        let library = dir.join("Library");
+
+        fs::create_dir_all(root.join("steamapps")).unwrap();
+        fs::write(root.join("steamapps/libraryfolders.vdf"), format!(
+            "\"LibraryFolders\"\n{{\n\t\"1\"\t\t\"{}\"\n}}\n", library.to_string_lossy()
+        )).unwrap();
+
+        let exe = library.join("steamapps/common/Borderlands 2").join(EXE_SUBPATH);
+        fs::create_dir_all(exe.parent().unwrap()).unwrap();
+        fs::write(&exe, b"MZ").unwrap();
+        fs::write(library.join(format!("steamapps/appmanifest_{}.acf", APPID)), format!(
+            "\"AppState\"\n{{\n\t\"appid\"\t\t\"{}\"\n\t\"installdir\"\t\t\"borderlands 2\"\n}}\n",
+            APPID
+        )).unwrap();
+
+        FIXTURE_ROOT.set(root.clone()).unwrap();
+        (guard, root, library)
+    }
+
+    // `FIXTURE_ROOT` can only be set once a run, so this is the one test to use it
+    #[test]
+    fn fixture_discovery() {
+        let (_guard, root, library) = fake_steam();
+        assert_eq!(existing_steam_roots().unwrap(), slice::from_ref(&root));
+
+        let layout = find_install_path(APPID, None).unwrap();
+        assert_eq!(layout.library_root, library);
+        assert_eq!(layout.steamapps, library.join("steamapps"));
+        assert_eq!(layout.install_dir, library.join("steamapps/common/Borderlands 2"));
+        assert_eq!(layout.exe_path, layout.install_dir.join(EXE_SUBPATH));
+
+        // the same, but insisting on it being the only install
+        let installs = find_all_installs(APPID).unwrap();
+        assert_eq!(installs.len(), 1);
+        assert_eq!(installs[0].0, root);
+        assert_eq!(installs[0].1.exe_path, layout.exe_path);
+
+        // and a game that isn't there
+        match find_install_path(APPID + 1, None) {
+            Err(e) => match e.downcast_ref() {
+                Some(PatcherError::CantFindManifest { appid, other_roots }) => {
+                    assert_eq!(*appid, APPID + 1);
+                    assert!(other_roots.is_empty());
+                }
+                _ => panic!("unexpected error: {}", e)
+            }
+            Ok(layout) => panic!("found a missing game at {}", layout.exe_path.to_string_lossy())
+        }
+    }
+
+    /// A build with no changes identified by `hashes`, unpatched and patched.
+    fn version_hashed(hashes: (&'static str, &'static str)) -> &'static Version {
+        Box::leak(Box::new(Version {
+            unpatched_hash: hashes.0,
+            patched_hash:   hashes.1,
+            ..*version_with(Vec::new())
+        }))
+    }
+
+    #[test]
+    fn duplicate_hashes() {
+        let a = "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+        let b = "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb";
+        let c = "cccccccccccccccccccccccccccccccccccccccc";
+        let d = "dddddddddddddddddddddddddddddddddddddddd";
+        let origins: Vec<String> = ["first.toml", "second.toml"].map(String::from).to_vec();
+
+        let distinct = [version_hashed((a, b)), version_hashed((c, d))];
+        assert!(defs::check_unique_hashes(&distinct, &origins).is_ok());
+
+        // one build's patched hash is another's unpatched
+        let shared = [version_hashed((a, b)), version_hashed((b, c))];
+        match defs::check_unique_hashes(&shared, &origins) {
+            Err(PatcherError::DuplicateHash { hash, first, second }) => {
+                assert_eq!(hash, b);
+                assert_eq!((first.as_str(), second.as_str()), ("first.toml", "second.toml"));
+            }
+            _ => panic!("duplicated hash not caught")
+        }
+
+        // even within a single build
+        assert!(defs::check_unique_hashes(&[version_hashed((a, a))], &origins).is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn undo_reverses_apply(
+            buffer    in vec(any::<u8>(), 1 .. 1024),
+            layout    in vec((0usize .. 64, vec(any::<u8>(), 1 .. 16)), 0 .. 16),
+            read_back in any::<bool>(),
+        ) {
+            let version = version_with(changes_over(&buffer, &layout));
+            let changes = version.changes.iter().map(|c| (c.offset, c.original, c.patch));
+            prop_assert!(check_changes(changes).is_ok());
+
+            let mut file = Cursor::new(buffer.clone());
+            version.modify_file(Action::Apply, &mut file, read_back).unwrap();
+            for change in version.changes {
+                let start = change.offset as usize;
+                prop_assert_eq!(&file.get_ref()[start .. start + change.patch.len()], change.patch);
+            }
+            version.modify_file(Action::Undo, &mut file, read_back).unwrap();
+            prop_assert_eq!(file.into_inner(), buffer);
+        }
+    }
 }
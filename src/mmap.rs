@@ -0,0 +1,38 @@
+// --mmap: the changes are written straight into a shared mapping of the executable and
+// flushed, rather than seeking to each one and writing it. Every change has to lie within
+// the file as it is, since a mapping can't grow it.
+
+use {
+    std::{
+        fs::File,
+        io,
+    },
+    memmap2::MmapMut,
+    crate::{Action, Version},
+};
+
+/// Maps all of `file` for writing.
+pub fn map(file: &File) -> io::Result<MmapMut> {
+    // SAFETY: the mapping is only used while we have the exe open for patching, and nothing
+    // else should be changing its size in the meantime; if something does, the worst is a
+    // failed patch, which the backup undoes
+    unsafe { MmapMut::map_mut(file) }
+}
+
+/// `Version::modify_file`, on a mapping, flushing it to the file before returning so the
+/// result can be verified by reading the file.
+pub fn modify(version: &Version, action: Action, map: &mut MmapMut) -> io::Result<()> {
+    for change in version.changes {
+        let bytes = match action {
+            Action::Apply => change.patch,
+            Action::Undo  => change.original
+        };
+        let start = change.offset as usize;
+        match map.get_mut(start .. start + bytes.len()) {
+            Some(target) => target.copy_from_slice(bytes),
+            None => return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                format!("the change at {:#x} is past the end of the file", change.offset)))
+        }
+    }
+    map.flush()
+}
@@ -0,0 +1,292 @@
+// --auto-offset: for an unknown build that's probably a minor rebuild of a known one. Each
+// change's original bytes, with a little context either side, are searched for near where
+// they sat in the known build; if every change turns up shifted by the same amount, the
+// patch can be applied at the shifted offsets.
+//
+// The context comes from the change's `before`/`after` bytes if its definition has them,
+// and otherwise from a full backup of the known build, if there is one beside the exe.
+//
+// --explain-unknown is the quick look before that: how many of the nearest known build's
+// changes still find their bytes right where that build had them.
+
+use {
+    std::{
+        collections::BTreeSet,
+        error::Error,
+        fs::{File, OpenOptions},
+        io::{Read, Seek, SeekFrom, Write},
+        path::Path,
+    },
+    crate::{
+        confirm, err_box, get_exe_state, hash_file, print_summary, refuse_writes, signed_hex,
+        write_error, Change, HashScope, PatcherError, Version, APPID,
+        backup::{Backup, BackupKind},
+        defs, history,
+    },
+};
+
+/// How far from its known offset a change is looked for, either way.
+const SEARCH_RADIUS: u64 = 0x1_0000;
+
+/// How many bytes either side of a change to take from a backup, when its definition
+/// doesn't record any.
+const CONTEXT_LEN: u64 = 16;
+
+/// How close in size to a known build a file has to be to be considered a rebuild of it,
+/// as a fraction of the known size.
+const SIZE_TOLERANCE: u64 = 50;
+
+/// A known build, and how far its changes have moved in the file being patched.
+struct Relocation {
+    version: &'static Version,
+    delta:   i64,
+}
+
+impl Relocation {
+    fn offset(&self, change: &Change) -> u64 {
+        (change.offset as i64 + self.delta) as u64
+    }
+}
+
+fn read_at(file: &mut File, offset: u64, len: u64) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut bytes = Vec::new();
+    file.seek(SeekFrom::Start(offset))?;
+    file.take(len).read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// The bytes to search for to find a change.
+struct Signature {
+    pattern: Vec<u8>,
+    // where the change's original bytes start in `pattern`
+    lead:    u64,
+}
+
+fn signature(change: &Change, backup: Option<&mut File>) -> Result<Option<Signature>, Box<dyn Error>> {
+    let (before, after) = match (&change.context, backup) {
+        (Some(context), _) => (context.before.to_vec(), context.after.to_vec()),
+        (None, Some(backup)) => {
+            let start = change.offset.saturating_sub(CONTEXT_LEN);
+            let before = read_at(backup, start, change.offset - start)?;
+            let after = read_at(backup, change.offset + change.original.len() as u64, CONTEXT_LEN)?;
+            (before, after)
+        }
+        (None, None) => return Ok(None)
+    };
+
+    let mut pattern = before;
+    let lead = pattern.len() as u64;
+    pattern.extend_from_slice(change.original);
+    pattern.extend_from_slice(&after);
+    Ok(Some(Signature { pattern, lead }))
+}
+
+/// Every offset within `SEARCH_RADIUS` of `around` where `pattern` starts.
+fn search(file: &mut File, pattern: &[u8], around: u64) -> Result<Vec<u64>, Box<dyn Error>> {
+    let start = around.saturating_sub(SEARCH_RADIUS);
+    let window = read_at(file, start, around + SEARCH_RADIUS - start + pattern.len() as u64)?;
+
+    let found = window.windows(pattern.len())
+        .enumerate()
+        .filter(|(_, bytes)| *bytes == pattern)
+        .map(|(index, _)| start + index as u64)
+        .collect();
+    Ok(found)
+}
+
+/// How big the unpatched file of `version` is, if anything says.
+fn known_size(exe_path: &Path, version: &Version) -> Result<Option<u64>, Box<dyn Error>> {
+    if version.size.is_some() {
+        return Ok(version.size);
+    }
+    match Backup::find(exe_path, version.unpatched_hash)? {
+        Some(backup) if backup.kind == BackupKind::Full => Ok(Some(backup.path.metadata()?.len())),
+        _ => Ok(None)
+    }
+}
+
+/// Tries to find every change of `version` in `file` at one consistent shift.
+fn locate(file: &mut File, size: u64, exe_path: &Path, version: &'static Version)
+    -> Result<Result<Relocation, String>, Box<dyn Error>>
+{
+    let hash = version.unpatched_hash;
+    let mut backup = match Backup::find(exe_path, hash)? {
+        Some(backup) if backup.kind == BackupKind::Full => Some(File::open(&backup.path)?),
+        _ => None
+    };
+
+    let known_size = match known_size(exe_path, version)? {
+        Some(size) => size,
+        None => return Ok(Err(format!("{}: size not recorded, and no full backup", hash)))
+    };
+    if size.abs_diff(known_size) > known_size / SIZE_TOLERANCE {
+        return Ok(Err(format!("{}: {} bytes, too far from this file's {}", hash, known_size, size)));
+    }
+
+    let mut deltas: Option<BTreeSet<i64>> = None;
+    for change in version.changes {
+        let Signature { pattern, lead } = match signature(change, backup.as_mut())? {
+            Some(signature) => signature,
+            None => return Ok(Err(format!(
+                "{}: no context recorded for the change at {:#x}, and no full backup", hash, change.offset)))
+        };
+
+        let found: BTreeSet<i64> = search(file, &pattern, change.offset.saturating_sub(lead))?
+            .into_iter()
+            .map(|start| (start + lead) as i64 - change.offset as i64)
+            .collect();
+        let common: BTreeSet<i64> = match deltas {
+            Some(deltas) => deltas.intersection(&found).copied().collect(),
+            None => found
+        };
+        if common.is_empty() {
+            return Ok(Err(format!("{}: the change at {:#x} isn't nearby", hash, change.offset)));
+        }
+        deltas = Some(common);
+    }
+
+    let deltas = deltas.unwrap_or_default();
+    match deltas.len() {
+        1 => Ok(Ok(Relocation { version, delta: *deltas.iter().next().unwrap() })),
+        count => Ok(Err(format!("{}: {} possible shifts", hash, count)))
+    }
+}
+
+/// Patches an unknown build at offsets shifted from a known one, after asking.
+pub fn patch_relocated(exe_path: &Path) -> Result<(), Box<dyn Error>> {
+    refuse_writes()?;
+    let mut file = OpenOptions::new().read(true).write(true).open(exe_path)?;
+    let hash = hash_file(&mut file)?.to_string();
+    let size = file.metadata()?.len();
+
+    let mut reasons = Vec::new();
+    let mut relocation = None;
+    for &version in defs::known_versions() {
+        if version.changes.is_empty() {
+            continue;
+        }
+        match locate(&mut file, size, exe_path, version)? {
+            Ok(found) => {
+                relocation = Some(found);
+                break;
+            }
+            Err(reason) => reasons.push(reason)
+        }
+    }
+    let relocation = match relocation {
+        Some(relocation) => relocation,
+        None => return err_box(PatcherError::CantRelocate { hash, reasons })
+    };
+
+    eprintln!("Unknown build {} looks like build {} shifted by {} bytes:",
+        hash, relocation.version.label(), signed_hex(relocation.delta));
+    for change in relocation.version.changes {
+        eprintln!("    {:#010x} -> {:#010x}", change.offset, relocation.offset(change));
+    }
+    if !confirm("Patch it at the shifted offsets?")? {
+        eprintln!("Not patched.");
+        return Ok(());
+    }
+
+    // nothing vouches for this build, so always keep a full copy of it
+    let backup = match Backup::find_sound(exe_path, &hash)? {
+        Some(backup) if backup.kind == BackupKind::Full => backup,
+        _ => Backup::create_full(exe_path, &hash)?
+    };
+    eprintln!("Backup: {}", backup.path.to_string_lossy());
+
+    eprint!("Patching {} ...", exe_path.to_string_lossy());
+    let result: Result<(), Box<dyn Error>> = (|| {
+        for change in relocation.version.changes {
+            file.seek(SeekFrom::Start(relocation.offset(change)))?;
+            file.write_all(change.patch).map_err(|e| write_error(e, exe_path))?;
+        }
+        file.flush()?;
+
+        for change in relocation.version.changes {
+            if read_at(&mut file, relocation.offset(change), change.patch.len() as u64)? != change.patch {
+                return Err(format!("the change at {:#x} didn't take", relocation.offset(change)).into());
+            }
+        }
+        Ok(())
+    })();
+
+    if let Err(e) = result {
+        eprintln!();
+        drop(file);
+        eprint!("Restoring from {} ...", backup.path.to_string_lossy());
+        match backup.roll_back(exe_path) {
+            Ok(()) => eprintln!("OK!"),
+            Err(e) => eprintln!("{}\nYou should restore from your backup.", e)
+        }
+        return Err(e);
+    }
+
+    eprintln!("OK!");
+    let build = format!("{} shifted by {}", relocation.version.label(), signed_hex(relocation.delta));
+    history::record(APPID, exe_path, "patched", Some(build), &hash, relocation.version.changes);
+    print_summary(relocation.version.changes);
+    eprintln!("This build is still unknown; use --revert-to-build {} to undo.", hash);
+    Ok(())
+}
+
+/// Compares an unknown build with the known build nearest it in size, change by change at
+/// the known offsets. Writes nothing.
+pub fn explain_unknown(exe_path: &Path, scope: HashScope) -> Result<(), Box<dyn Error>> {
+    let mut file = File::open(exe_path)?;
+    if let Ok(state) = get_exe_state(&mut file, scope) {
+        eprintln!("{} is known build {}; there's nothing to explain.",
+            exe_path.to_string_lossy(), state.version.label());
+        return Ok(());
+    }
+    let hash = hash_file(&mut file)?.to_string();
+    let size = file.metadata()?.len();
+
+    // builds of unknown size go last
+    let mut candidates = Vec::new();
+    for &version in defs::known_versions() {
+        if !version.changes.is_empty() {
+            candidates.push((known_size(exe_path, version)?, version));
+        }
+    }
+    candidates.sort_by_key(|&(known_size, _)| known_size.map_or(u64::MAX, |known| known.abs_diff(size)));
+    let (known_size, version) = match candidates.first() {
+        Some(&candidate) => candidate,
+        None => return Err("no known build has any changes to compare with".into())
+    };
+
+    println!("Unknown build {}, {} bytes", hash, size);
+    match known_size {
+        Some(known_size) => println!("Nearest known build: {}, {} bytes ({:+})",
+            version.label(), known_size, size as i64 - known_size as i64),
+        None => println!("Nearest known build: {}, of unrecorded size", version.label())
+    }
+
+    let (mut original, mut patched) = (0, 0);
+    for change in version.changes {
+        let found = read_at(&mut file, change.offset, change.original.len() as u64)?;
+        if found == change.original {
+            original += 1;
+        }
+        else if found == change.patch {
+            patched += 1;
+        }
+    }
+    let total = version.changes.len();
+    println!("{} of {} changes still have their original bytes at the known offsets, and {} their patched bytes",
+        original, total, patched);
+
+    if original + patched == total {
+        println!("The patch still lines up; this is most likely that build with changes elsewhere \
+            (a new signature, say). --auto-offset should be able to patch it.");
+    }
+    else if 2 * (original + patched) >= total {
+        println!("Most of the patch lines up; this is probably a minor rebuild. --auto-offset may \
+            be able to patch it.");
+    }
+    else {
+        println!("Little of the patch lines up; if --auto-offset can't find it shifted, this is a \
+            different build altogether and needs its own definition.");
+    }
+    Ok(())
+}
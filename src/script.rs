@@ -0,0 +1,158 @@
+// --emit-script: the detected build's patch (or unpatch) as a standalone script that does
+// exactly what this tool would, for reading before running, or for running where the tool
+// can't be. Each script checks every change's current bytes before writing any, backs the
+// exe up first when patching (under the name --restore looks for), and checks the result's
+// SHA1 where it can.
+//
+//     sh:  dd to read and write at each offset, od to compare, sha1sum if there is one
+//     ps1: a FileStream, since Set-Content can only write whole files; Get-FileHash
+
+use {
+    std::fmt::Write as _,
+    crate::{to_hex, Action, Change, Version},
+};
+
+/// The bytes `action` expects at a change, and the bytes it writes there.
+fn bytes(change: &Change, action: Action) -> (&'static [u8], &'static [u8]) {
+    match action {
+        Action::Apply => (change.original, change.patch),
+        Action::Undo  => (change.patch, change.original),
+    }
+}
+
+fn describe(version: &Version, action: Action) -> (&'static str, &'static str) {
+    match action {
+        Action::Apply => ("Patches", version.patched_hash),
+        Action::Undo  => ("Unpatches", version.unpatched_hash),
+    }
+}
+
+pub fn sh(version: &Version, action: Action) -> String {
+    let (verb, expected_hash) = describe(version, action);
+    let mut script = String::new();
+    let _ = write!(script, r#"#!/bin/sh
+# {verb} Borderlands 2 build {label} (unpatched SHA1 {unpatched}), as bl-patcher would.
+# Usage: sh this-script.sh path/to/Borderlands2.exe
+set -e
+exe="${{1:?usage: $0 EXE}}"
+
+# offset (decimal) and length, hex bytes expected
+check() {{
+    found=$(dd if="$exe" bs=1 skip="$1" count="$2" 2>/dev/null | od -An -v -tx1 | tr -d ' \n')
+    if [ "$found" != "$3" ]; then
+        echo "$exe: expected $3 at offset $1 but found $found, so it isn't the expected build; nothing written" >&2
+        exit 1
+    fi
+}}
+
+# offset (decimal), bytes as printf octal escapes
+write() {{
+    printf "$2" | dd of="$exe" bs=1 seek="$1" conv=notrunc 2>/dev/null
+}}
+
+"#, verb = verb, label = version.label(), unpatched = version.unpatched_hash);
+
+    for change in version.changes {
+        let (expected, _) = bytes(change, action);
+        let _ = writeln!(script, "check {} {} {}    # {:#x}",
+            change.offset, expected.len(), to_hex(expected), change.offset);
+    }
+
+    if let Action::Apply = action {
+        let _ = write!(script, r#"
+backup="$exe.{hash}.bak"
+[ -e "$backup" ] || cp -p "$exe" "$backup"
+echo "Backup: $backup" >&2
+"#, hash = version.unpatched_hash);
+    }
+
+    script.push('\n');
+    for change in version.changes {
+        let (_, written) = bytes(change, action);
+        let octal: String = written.iter().map(|byte| format!("\\{:03o}", byte)).collect();
+        let _ = writeln!(script, "write {} '{}'    # {:#x}", change.offset, octal, change.offset);
+    }
+
+    let _ = write!(script, r#"
+if command -v sha1sum >/dev/null 2>&1; then
+    hash=$(sha1sum < "$exe" | cut -c 1-40)
+    if [ "$hash" != {expected} ]; then
+        echo "$exe: written, but it hashes as $hash rather than {expected}" >&2
+        exit 1
+    fi
+fi
+echo "OK: $exe" >&2
+"#, expected = expected_hash);
+    script
+}
+
+/// A PowerShell byte array literal.
+fn ps_bytes(bytes: &[u8]) -> String {
+    let bytes: Vec<String> = bytes.iter().map(|byte| format!("0x{:02x}", byte)).collect();
+    format!("[byte[]]({})", bytes.join(", "))
+}
+
+pub fn ps1(version: &Version, action: Action) -> String {
+    let (verb, expected_hash) = describe(version, action);
+    let mut script = String::new();
+    let _ = write!(script, r#"# {verb} Borderlands 2 build {label} (unpatched SHA1 {unpatched}), as bl-patcher would.
+# Usage: powershell -File this-script.ps1 path\to\Borderlands2.exe
+param([Parameter(Mandatory = $true)][string]$Exe)
+$ErrorActionPreference = 'Stop'
+$Exe = (Resolve-Path $Exe).Path
+
+# offset, bytes expected, bytes written
+$changes = @()
+"#, verb = verb, label = version.label(), unpatched = version.unpatched_hash);
+
+    for change in version.changes {
+        let (expected, written) = bytes(change, action);
+        let _ = writeln!(script, "$changes += ,@({:#x}, {}, {})",
+            change.offset, ps_bytes(expected), ps_bytes(written));
+    }
+
+    let _ = write!(script, r#"
+$file = [System.IO.File]::Open($Exe, 'Open', 'Read')
+try {{
+    foreach ($change in $changes) {{
+        $found = New-Object byte[] $change[1].Length
+        $file.Position = $change[0]
+        $read = $file.Read($found, 0, $found.Length)
+        if ($read -ne $found.Length -or [Convert]::ToBase64String($found) -ne [Convert]::ToBase64String($change[1])) {{
+            throw ('{{0}}: unexpected bytes at offset 0x{{1:x}}, so it isn''t the expected build; nothing written' -f $Exe, $change[0])
+        }}
+    }}
+}}
+finally {{
+    $file.Close()
+}}
+"#);
+
+    if let Action::Apply = action {
+        let _ = write!(script, r#"
+$backup = "$Exe.{hash}.bak"
+if (-not (Test-Path $backup)) {{ Copy-Item $Exe $backup }}
+Write-Host "Backup: $backup"
+"#, hash = version.unpatched_hash);
+    }
+
+    let _ = write!(script, r#"
+$file = [System.IO.File]::Open($Exe, 'Open', 'ReadWrite')
+try {{
+    foreach ($change in $changes) {{
+        $file.Position = $change[0]
+        $file.Write($change[2], 0, $change[2].Length)
+    }}
+}}
+finally {{
+    $file.Close()
+}}
+
+$hash = (Get-FileHash -Algorithm SHA1 $Exe).Hash.ToLower()
+if ($hash -ne '{expected}') {{
+    throw "${{Exe}}: written, but it hashes as $hash rather than {expected}"
+}}
+Write-Host "OK: $Exe"
+"#, expected = expected_hash);
+    script
+}
@@ -0,0 +1,97 @@
+// A long-running mode for launchers and overlays: listens on a Unix socket and answers one
+// JSON request per line, one JSON response per line.
+//
+//     {"request": "status"}  ->  {"ok": true, "state": "unpatched", "hash": "bc1d…"}
+//     {"request": "apply"}   ->  {"ok": true, "state": "patched", "hash": "fc8a…"}
+//     {"request": "undo"}    ->  {"ok": true, "state": "unpatched", "hash": "bc1d…"}
+//     anything that fails    ->  {"ok": false, "error": "…"}
+//
+// Connections are handled one at a time, so two clients can never patch at once.
+//
+// Unix only for now: there's no Windows named-pipe listener yet, so --serve on Windows fails
+// up front rather than listening on anything.
+
+use {
+    std::{
+        error::Error,
+        fs::{self, File},
+        io::{BufRead, BufReader, Write},
+        os::unix::net::{UnixListener, UnixStream},
+        path::Path,
+    },
+    serde_json::{json, Value},
+    crate::{get_exe_state, modify_exe, open_exe, with_writable, Action, Options},
+};
+
+fn status(exe_path: &Path, options: &Options) -> Result<Value, Box<dyn Error>> {
+    let state = get_exe_state(&mut File::open(exe_path)?, options.hash_scope())?;
+    let (state, hash) = if state.patched {
+        ("patched", state.version.patched_hash)
+    }
+    else {
+        ("unpatched", state.version.unpatched_hash)
+    };
+    Ok(json!({ "ok": true, "state": state, "hash": hash }))
+}
+
+fn set_patched(exe_path: &Path, patched: bool, options: &Options) -> Result<Value, Box<dyn Error>> {
+    // as on the command line, so a read-only exe is refused or, with --clear-readonly, made
+    // writable just for the duration
+    with_writable(exe_path, options.clear_readonly, || {
+        // --assume-* only speaks for the exe as it was at startup, so each request hashes it
+        let (file, state) = open_exe(exe_path, options.hash_scope(), None)?;
+        if state.patched != patched {
+            let action = if patched { Action::Apply } else { Action::Undo };
+            modify_exe(exe_path, file, &state, action, options)?;
+        }
+        Ok(())
+    })?;
+    status(exe_path, options)
+}
+
+fn respond(line: &str, exe_path: &Path, options: &Options) -> Value {
+    let request: Value = match serde_json::from_str(line) {
+        Ok(request) => request,
+        Err(e) => return json!({ "ok": false, "error": format!("bad request: {}", e) })
+    };
+
+    let result = match request.get("request").and_then(Value::as_str) {
+        Some("status") => status(exe_path, options),
+        Some("apply")  => set_patched(exe_path, true, options),
+        Some("undo")   => set_patched(exe_path, false, options),
+        _ => return json!({ "ok": false, "error": "unknown request" })
+    };
+
+    result.unwrap_or_else(|e| json!({ "ok": false, "error": e.to_string() }))
+}
+
+fn handle(stream: UnixStream, exe_path: &Path, options: &Options) -> Result<(), Box<dyn Error>> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = respond(&line, exe_path, options);
+        writeln!(writer, "{}", response)?;
+    }
+    Ok(())
+}
+
+pub fn serve(socket: &Path, exe_path: &Path, options: &Options) -> Result<(), Box<dyn Error>> {
+    // a socket file left over from an earlier run would make bind fail
+    if socket.exists() && UnixStream::connect(socket).is_err() {
+        fs::remove_file(socket)?;
+    }
+
+    let listener = UnixListener::bind(socket)?;
+    eprintln!("Serving {} on {}", exe_path.to_string_lossy(), socket.to_string_lossy());
+
+    for stream in listener.incoming() {
+        // one bad client shouldn't take the server down
+        if let Err(e) = stream.map_err(Into::into).and_then(|stream| handle(stream, exe_path, options)) {
+            eprintln!("Connection error: {}", e);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,144 @@
+// --shortcuts: finding the executable through Steam's non-Steam shortcuts, for setups that
+// launch a copy of the game somewhere else entirely. Each Steam user's shortcuts are in
+// <steam root>/userdata/<user>/config/shortcuts.vdf, which is binary KeyValues:
+//
+//     <type, 1 byte> <key, NUL-terminated> <value>  ...  0x08 (end of table)
+//
+// where a table's value (type 0x00) is more of the same, a string's (0x01) is NUL-terminated,
+// and numbers are little-endian. The file holds one table, "shortcuts", keyed "0", "1", ...,
+// each with an "Exe": the quoted path of what it launches.
+
+use {
+    std::{
+        error::Error,
+        fs,
+        path::{Path, PathBuf},
+    },
+    crate::{err_box, existing_steam_roots, ArchFilter, HashScope, InstallLayout, PatcherError},
+};
+
+enum Value {
+    Table(Vec<(String, Value)>),
+    String(String),
+    // numbers, which nothing here needs
+    Other,
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Option<&'a [u8]> {
+        if self.bytes.len() < count {
+            return None;
+        }
+        let (taken, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+        Some(taken)
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let end = self.bytes.iter().position(|&byte| byte == 0)?;
+        let string = String::from_utf8_lossy(&self.bytes[.. end]).to_string();
+        self.bytes = &self.bytes[end + 1 ..];
+        Some(string)
+    }
+
+    /// A table's entries, up to its end marker. The file's own outermost end marker is
+    /// sometimes missing, so `outermost` also accepts the end of the file.
+    fn table(&mut self, outermost: bool) -> Option<Vec<(String, Value)>> {
+        let mut entries = Vec::new();
+        loop {
+            let kind = match self.take(1) {
+                Some(kind) => kind[0],
+                None if outermost => return Some(entries),
+                None => return None
+            };
+            if kind == 0x08 {
+                return Some(entries);
+            }
+
+            let key = self.string()?;
+            let value = match kind {
+                0x00 => Value::Table(self.table(false)?),
+                0x01 => Value::String(self.string()?),
+                // int32, float32, pointer, colour
+                0x02 | 0x03 | 0x04 | 0x06 => { self.take(4)?; Value::Other }
+                // uint64, int64
+                0x07 | 0x0a => { self.take(8)?; Value::Other }
+                _ => return None
+            };
+            entries.push((key, value));
+        }
+    }
+}
+
+/// The value of `key` in `table`. Steam has capitalized the keys differently over the years.
+fn get<'a>(table: &'a [(String, Value)], key: &str) -> Option<&'a Value> {
+    table.iter().find(|(k, _)| k.eq_ignore_ascii_case(key)).map(|(_, value)| value)
+}
+
+/// What each shortcut in the file at `path` launches, in the file's order.
+fn shortcut_exes(path: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let bytes = fs::read(path)?;
+    let root = match (Reader { bytes: &bytes }).table(true) {
+        Some(root) => root,
+        None => return err_box(PatcherError::BadVDF { path: path.to_string_lossy().to_string() })
+    };
+
+    let shortcuts = match get(&root, "shortcuts") {
+        Some(Value::Table(shortcuts)) => shortcuts,
+        _ => return Ok(Vec::new())
+    };
+
+    Ok(shortcuts.iter()
+        .filter_map(|(_, shortcut)| match shortcut {
+            Value::Table(shortcut) => match get(shortcut, "exe") {
+                Some(Value::String(exe)) => Some(PathBuf::from(exe.trim_matches('"'))),
+                _ => None
+            },
+            _ => None
+        })
+        .filter(|exe| !exe.as_os_str().is_empty())
+        .collect())
+}
+
+/// The first executable any Steam user's shortcuts launch that `filter` admits and that's a
+/// build we know, under `steam_root` or, failing that, every Steam installation here.
+pub fn find_known(steam_root: Option<&Path>, scope: HashScope, filter: &mut ArchFilter)
+    -> Result<Option<InstallLayout>, Box<dyn Error>>
+{
+    let roots = match steam_root {
+        Some(root) => vec![root.canonicalize()?],
+        None => existing_steam_roots()?
+    };
+
+    for root in roots {
+        let users = match fs::read_dir(root.join("userdata")) {
+            Ok(users) => users,
+            Err(_) => continue
+        };
+        let mut files: Vec<PathBuf> = users
+            .filter_map(|entry| Some(entry.ok()?.path().join("config/shortcuts.vdf")))
+            .filter(|path| path.is_file())
+            .collect();
+        files.sort();
+
+        for file in files {
+            // one user's broken shortcuts shouldn't hide another's
+            let exes = match shortcut_exes(&file) {
+                Ok(exes) => exes,
+                Err(e) => {
+                    note!("Warning: skipping {}: {}", file.to_string_lossy(), e);
+                    continue;
+                }
+            };
+            if let Some(exe) = exes.into_iter().find(|exe| filter.admits_known(exe, scope)) {
+                note!("Found a known build through a Steam shortcut, at {}", exe.to_string_lossy());
+                return Ok(Some(InstallLayout::for_exe(exe)));
+            }
+        }
+    }
+    Ok(None)
+}